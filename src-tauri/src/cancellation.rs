@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// In-flight generations that can be cancelled from the frontend, keyed by request id.
+/// Managed as Tauri state; `cancel_generation` flips the flag the generation is waiting on.
+#[derive(Default)]
+pub struct CancellationRegistry {
+    requests: Mutex<HashMap<String, watch::Sender<bool>>>,
+}
+
+/// Receiving side of a registered request; see [`cancelled`]. Clones observe the same
+/// cancellation, for work that fans out.
+#[derive(Clone)]
+pub struct CancelToken {
+    receiver: watch::Receiver<bool>,
+}
+
+impl CancellationRegistry {
+    /// Register `request_id` and return the token the generation should watch.
+    /// Re-registering an id replaces (and thereby detaches) the previous token.
+    pub fn register(&self, request_id: &str) -> CancelToken {
+        let (sender, receiver) = watch::channel(false);
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(request_id.to_string(), sender);
+        CancelToken { receiver }
+    }
+
+    /// Signal cancellation. Returns false if no generation with this id is running.
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.requests.lock().unwrap_or_else(|e| e.into_inner()).get(request_id) {
+            Some(sender) => sender.send(true).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn remove(&self, request_id: &str) {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(request_id);
+    }
+}
+
+/// Resolves once `token` is cancelled; never resolves for `None` or a token whose
+/// registration has been removed. Meant to be raced against work in `tokio::select!`.
+pub async fn cancelled(token: &mut Option<CancelToken>) {
+    if let Some(token) = token {
+        if token.receiver.wait_for(|cancelled| *cancelled).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiter() {
+        let registry = CancellationRegistry::default();
+        let mut token = Some(registry.register("req-1"));
+
+        assert!(registry.cancel("req-1"));
+        tokio::time::timeout(Duration::from_secs(1), cancelled(&mut token))
+            .await
+            .expect("cancelled() should resolve after cancel");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_and_removed_requests() {
+        let registry = CancellationRegistry::default();
+        assert!(!registry.cancel("missing"));
+
+        let mut token = Some(registry.register("req-2"));
+        registry.remove("req-2");
+        assert!(!registry.cancel("req-2"));
+
+        // A removed registration never fires.
+        let waited = tokio::time::timeout(Duration::from_millis(50), cancelled(&mut token)).await;
+        assert!(waited.is_err());
+
+        let mut none = None;
+        let waited = tokio::time::timeout(Duration::from_millis(50), cancelled(&mut none)).await;
+        assert!(waited.is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::ai_providers::{AIRequest, AIResponse, TokenUsage};
+use crate::structured_output;
+
+/// Most candidates one request may ask for; each is billed like a separate answer.
+pub const MAX_CANDIDATES: u32 = 5;
+
+/// Ask for several independent answers to the same request instead of one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CandidateOptions {
+    pub count: u32,
+    #[serde(default)]
+    pub ranking: Ranking,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking {
+    /// Keep the provider's order.
+    #[default]
+    None,
+    /// Score locally; see `heuristic_scores`.
+    Heuristic,
+    /// Ask the same model to score them, at the cost of one more call.
+    Model,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub content: String,
+    /// The answer parsed as JSON, when the request had a `response_schema` and it matched.
+    #[serde(default)]
+    pub parsed: Option<Value>,
+    /// Why the answer doesn't match the schema.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// 0 to 1, higher is better; `None` until ranked.
+    #[serde(default)]
+    pub score: Option<f32>,
+}
+
+impl Candidate {
+    /// Candidates for the answers of one call; none for a single answer.
+    pub fn from_texts(texts: Vec<String>) -> Vec<Candidate> {
+        if texts.len() < 2 {
+            return Vec::new();
+        }
+        texts.into_iter()
+            .map(|content| Candidate { content, parsed: None, error: None, score: None })
+            .collect()
+    }
+}
+
+/// How many answers `request` asks for.
+pub fn count(request: &AIRequest) -> u32 {
+    request.candidates.as_ref().map_or(1, |c| c.count.clamp(1, MAX_CANDIDATES))
+}
+
+pub fn ranking(request: &AIRequest) -> Ranking {
+    request.candidates.as_ref().map_or(Ranking::None, |c| c.ranking)
+}
+
+/// Combine the responses of one call per candidate. The usage is the sum, since each
+/// call was billed for the prompt.
+pub fn merge(mut responses: Vec<AIResponse>) -> AIResponse {
+    let mut usage = TokenUsage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 };
+    for u in responses.iter().filter_map(|r| r.usage.as_ref()) {
+        usage.prompt_tokens += u.prompt_tokens;
+        usage.completion_tokens += u.completion_tokens;
+        usage.total_tokens += u.total_tokens;
+    }
+    let texts = responses.iter().map(|r| r.content.clone()).collect();
+    let mut merged = responses.swap_remove(0);
+    merged.usage = Some(usage);
+    merged.candidates = Candidate::from_texts(texts);
+    merged
+}
+
+/// Validate each candidate against `schema` and make the first valid one the response's
+/// content, so one malformed answer doesn't fail the others.
+pub fn validate(response: &mut AIResponse, schema: &Value) {
+    for candidate in &mut response.candidates {
+        match structured_output::check(&candidate.content, schema) {
+            Ok(value) => candidate.parsed = Some(value),
+            Err(e) => candidate.error = Some(e),
+        }
+    }
+    if let Some(valid) = response.candidates.iter().find(|c| c.error.is_none()) {
+        response.content = valid.content.clone();
+    }
+}
+
+/// Without a model to judge them: empty answers and ones that don't match the schema
+/// score 0; the rest score higher the less they repeat the other candidates and the
+/// closer they come to the typical candidate's length.
+pub fn heuristic_scores(candidates: &[Candidate]) -> Vec<f32> {
+    let words: Vec<HashSet<String>> = candidates.iter().map(|c| word_set(&c.content)).collect();
+    let mut lengths: Vec<usize> = candidates.iter().map(|c| c.content.trim().len()).collect();
+    lengths.sort_unstable();
+    let median = lengths.get(lengths.len() / 2).copied().unwrap_or(0).max(1);
+
+    candidates.iter()
+        .enumerate()
+        .map(|(i, candidate)| {
+            if candidate.error.is_some() || candidate.content.trim().is_empty() {
+                return 0.0;
+            }
+            let overlap = words.iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, other)| jaccard(&words[i], other))
+                .fold(0.0, f32::max);
+            let length = (candidate.content.trim().len() as f32 / median as f32).min(1.0);
+            0.5 * (1.0 - overlap) + 0.5 * length
+        })
+        .collect()
+}
+
+fn word_set(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+/// Request asking the model to score `candidates` as answers to `request`.
+pub fn ranking_request(request: &AIRequest, candidates: &[Candidate]) -> AIRequest {
+    let answers = candidates.iter()
+        .enumerate()
+        .map(|(i, c)| format!("Candidate {}:\n\"\"\"\n{}\n\"\"\"", i + 1, c.content.trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    AIRequest {
+        prompt: format!(
+            "Score each of these {} candidate answers to the request below from 1 to 10 for how well it fulfils it: \
+             relevance, specificity, correctness and usefulness. Judge each on its own merits.\n\nRequest:\n\"\"\"\n{}\n\"\"\"\n\n{}",
+            candidates.len(),
+            request.prompt.trim(),
+            answers
+        ),
+        system_message: Some("You are an impartial judge comparing candidate answers.".to_string()),
+        temperature: Some(0.0),
+        max_tokens: Some(100 + 20 * candidates.len() as u32),
+        messages: vec![],
+        response_schema: Some(serde_json::json!({
+            "type": "object",
+            "properties": {
+                "scores": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "candidate": {"type": "integer"},
+                            "score": {"type": "number"}
+                        },
+                        "required": ["candidate", "score"]
+                    }
+                }
+            },
+            "required": ["scores"]
+        })),
+        split_input: None,
+        sampling: Default::default(),
+        candidates: None,
+        // The answers are judged as text; resending the images or functions would only add cost.
+        images: vec![],
+        tools: vec![],
+        ..request.clone()
+    }
+}
+
+/// The judge's scores scaled to 0 to 1, in candidate order; `None` unless every
+/// candidate got one.
+pub fn parse_scores(parsed: &Value, count: usize) -> Option<Vec<f32>> {
+    let mut scores = vec![None; count];
+    for entry in parsed["scores"].as_array()? {
+        let index = entry["candidate"].as_u64()? as usize;
+        let score = entry["score"].as_f64()? as f32;
+        *scores.get_mut(index.checked_sub(1)?)? = Some((score / 10.0).clamp(0.0, 1.0));
+    }
+    scores.into_iter().collect()
+}
+
+/// Sort the candidates best first and make the best one the response's answer. Answers
+/// that don't match the schema always rank last.
+pub fn order(response: &mut AIResponse, scores: &[f32]) {
+    for (candidate, score) in response.candidates.iter_mut().zip(scores) {
+        candidate.score = Some(if candidate.error.is_some() { 0.0 } else { *score });
+    }
+    response.candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    if let Some(best) = response.candidates.first() {
+        response.content = best.content.clone();
+        response.parsed = best.parsed.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(texts: &[&str]) -> Vec<Candidate> {
+        Candidate::from_texts(texts.iter().map(|t| t.to_string()).collect())
+    }
+
+    fn response(texts: &[&str]) -> AIResponse {
+        AIResponse {
+            content: texts[0].to_string(),
+            provider: "mock".to_string(),
+            model: "mock-model".to_string(),
+            success: true,
+            error: None,
+            usage: Some(TokenUsage { prompt_tokens: 10, completion_tokens: 5, total_tokens: 15 }),
+            error_type: None,
+            fallbacks: Vec::new(),
+            parsed: None,
+            cached: false,
+            error_details: None,
+            sampling: None,
+            candidates: candidates(texts),
+            tool_calls: Vec::new(),
+            tool_runs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_heuristic_prefers_distinct_complete_answers() {
+        let scores = heuristic_scores(&candidates(&[
+            "A bakery subscription delivering fresh sourdough every week",
+            "A bakery subscription delivering fresh sourdough every week",
+            "Surplus bread sold at a discount through a pickup app for students",
+            "Bread",
+            "",
+        ]));
+        assert!(scores[2] > scores[0] && scores[0] == scores[1]);
+        assert!(scores[3] < scores[2]);
+        assert_eq!(scores[4], 0.0);
+    }
+
+    #[test]
+    fn test_merge_validate_and_order() {
+        let single = |text: &str| AIResponse { candidates: Vec::new(), ..response(&[text]) };
+        let mut merged = merge(vec![single("not json"), single(r#"{"name": "Crumbs"}"#), single(r#"{"name": "Loaf Club"}"#)]);
+        assert_eq!(merged.usage.as_ref().unwrap().total_tokens, 45);
+        assert_eq!(merged.candidates.len(), 3);
+
+        let schema = serde_json::json!({"type": "object", "required": ["name"]});
+        validate(&mut merged, &schema);
+        assert!(merged.candidates[0].error.is_some());
+        assert_eq!(merged.content, r#"{"name": "Crumbs"}"#);
+
+        let judged = serde_json::json!({"scores": [
+            {"candidate": 1, "score": 10}, {"candidate": 2, "score": 6}, {"candidate": 3, "score": 9}
+        ]});
+        let scores = parse_scores(&judged, 3).unwrap();
+        assert!(parse_scores(&judged, 4).is_none());
+        order(&mut merged, &scores);
+        assert_eq!(merged.parsed.as_ref().unwrap()["name"], "Loaf Club");
+        assert_eq!(merged.candidates[2].score, Some(0.0));
+    }
+}
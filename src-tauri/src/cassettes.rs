@@ -0,0 +1,355 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use crate::secure_storage;
+
+/// Directory of cassette files, one `{provider}.json` per provider.
+pub const CASSETTE_DIR_VAR: &str = "AI_CASSETTE_DIR";
+/// "record" or "replay"; anything else (or unset) talks to the network as usual.
+pub const CASSETTE_MODE_VAR: &str = "AI_CASSETTE_MODE";
+
+/// Stands in for API keys in recorded fixtures, and is the key providers use in replay.
+pub const REDACTED: &str = "REDACTED";
+
+/// Serialises recording so concurrent requests don't overwrite each other's additions.
+static RECORD_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    /// Send requests for real and append each exchange to the cassette.
+    Record,
+    /// Answer from the cassette without touching the network.
+    Replay,
+}
+
+/// One recorded exchange. Request headers are not kept (they carry the key); the
+/// request is matched on method, URL and body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    /// Parsed JSON where possible, so fixtures diff readably.
+    pub body: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// The full body; for streaming requests, the raw SSE / NDJSON text.
+    pub body: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    /// Replay only: the cassette's interactions, read on the first replay, and which of
+    /// them this client has served.
+    interactions: tokio::sync::OnceCell<Vec<Interaction>>,
+    used: Mutex<HashSet<usize>>,
+}
+
+/// The HTTP client every provider sends through. Normally a plain `reqwest::Client`;
+/// with `AI_CASSETTE_MODE` set it records exchanges to, or replays them from, redacted
+/// fixture files, so response parsing can be regression-tested offline. The fixtures in
+/// `cassettes/` are hand-written until someone records real ones.
+pub struct HttpClient {
+    client: reqwest::Client,
+    cassette: Option<Cassette>,
+    /// Used instead of the stored key by tests against local servers.
+    #[cfg(test)]
+    api_key: Option<String>,
+}
+
+impl HttpClient {
+    pub fn new(provider: &str, timeout: std::time::Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        let mode = match std::env::var(CASSETTE_MODE_VAR).as_deref() {
+            Ok("record") => Some(CassetteMode::Record),
+            Ok("replay") => Some(CassetteMode::Replay),
+            _ => None,
+        };
+        let dir = std::env::var(CASSETTE_DIR_VAR).unwrap_or_else(|_| "cassettes".to_string());
+        let cassette = mode.map(|mode| Cassette::open(mode, Path::new(&dir).join(format!("{}.json", provider))));
+
+        Self {
+            client,
+            cassette,
+            #[cfg(test)]
+            api_key: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_api_key(self, key: &str) -> Self {
+        Self { api_key: Some(key.to_string()), ..self }
+    }
+
+    /// Replay from the given interactions instead of the network.
+    #[cfg(test)]
+    pub fn replaying(interactions: Vec<Interaction>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            cassette: Some(Cassette {
+                mode: CassetteMode::Replay,
+                path: PathBuf::new(),
+                interactions: tokio::sync::OnceCell::new_with(Some(interactions)),
+                used: Mutex::new(HashSet::new()),
+            }),
+            api_key: None,
+        }
+    }
+
+    pub fn get<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// The provider's stored key. Replays need no real key, so they get a placeholder.
+    pub fn api_key(&self, provider: &str) -> Result<Option<String>, String> {
+        #[cfg(test)]
+        if let Some(key) = &self.api_key {
+            return Ok(Some(key.clone()));
+        }
+        match &self.cassette {
+            Some(cassette) if cassette.mode == CassetteMode::Replay => Ok(Some(REDACTED.to_string())),
+            _ => secure_storage::retrieve_api_key(provider),
+        }
+    }
+
+    /// Send the request. Errors are transport failures (or a replay with no matching
+    /// recording); HTTP error statuses come back as responses.
+    pub async fn send(&self, builder: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        let Some(cassette) = &self.cassette else {
+            return builder.send().await.map_err(|e| e.to_string());
+        };
+
+        let request = builder.build().map_err(|e| e.to_string())?;
+        let secrets = secrets_of(&request);
+        let recorded = RecordedRequest {
+            method: request.method().to_string(),
+            url: redact(request.url().as_str(), &secrets),
+            body: request.body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| {
+                    let text = redact(&String::from_utf8_lossy(bytes), &secrets);
+                    serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+                }),
+        };
+
+        match cassette.mode {
+            CassetteMode::Replay => cassette.replay(&recorded).await,
+            CassetteMode::Record => {
+                let response = self.client.execute(request).await.map_err(|e| e.to_string())?;
+                let status = response.status().as_u16();
+                let content_type = response.headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string());
+                // Streams are read to the end here and handed on as a single chunk.
+                let body = response.bytes().await.map_err(|e| e.to_string())?;
+
+                let recording = RecordedResponse {
+                    status,
+                    content_type,
+                    body: redact_key_like(&redact(&String::from_utf8_lossy(&body), &secrets)),
+                };
+                let path = cassette.path.clone();
+                let interaction = Interaction { request: recorded, response: recording.clone() };
+                match tokio::task::spawn_blocking(move || Cassette::record(&path, interaction)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => eprintln!("Failed to record cassette: {}", e),
+                    Err(e) => eprintln!("Failed to record cassette: {}", e),
+                }
+                Ok(to_response(&RecordedResponse { body: String::from_utf8_lossy(&body).to_string(), ..recording }))
+            }
+        }
+    }
+}
+
+impl Cassette {
+    fn open(mode: CassetteMode, path: PathBuf) -> Self {
+        Self { mode, path, interactions: tokio::sync::OnceCell::new(), used: Mutex::new(HashSet::new()) }
+    }
+
+    async fn interactions(&self) -> &[Interaction] {
+        self.interactions.get_or_init(|| async {
+            let path = self.path.clone();
+            let loaded = tokio::task::spawn_blocking(move || load(&path))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|loaded| loaded);
+            loaded.unwrap_or_else(|e| {
+                eprintln!("Failed to load cassette {}: {}", self.path.display(), e);
+                Vec::new()
+            })
+        }).await
+    }
+
+    /// The first interaction matching `request` that this client hasn't served yet, so a
+    /// recorded 429 followed by a 200 replays in that order. Once all matches have been
+    /// used, the last one is repeated.
+    async fn replay(&self, request: &RecordedRequest) -> Result<reqwest::Response, String> {
+        let interactions = self.interactions().await;
+        let matches: Vec<usize> = interactions.iter()
+            .enumerate()
+            .filter(|(_, interaction)| interaction.request == *request)
+            .map(|(i, _)| i)
+            .collect();
+
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        let index = matches.iter()
+            .find(|i| !used.contains(*i))
+            .or(matches.last())
+            .copied()
+            .ok_or_else(|| format!("No recorded response for {} {}", request.method, request.url))?;
+        used.insert(index);
+
+        Ok(to_response(&interactions[index].response))
+    }
+
+    /// Append `interaction` to the cassette at `path`. Blocking; run it off the runtime.
+    fn record(path: &Path, interaction: Interaction) -> Result<(), String> {
+        let _guard = RECORD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut interactions = if path.exists() { load(path)? } else { Vec::new() };
+        interactions.push(interaction);
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create cassette directory: {}", e))?;
+        }
+        let contents = serde_json::to_string_pretty(&CassetteFile { interactions })
+            .map_err(|e| format!("Failed to serialize cassette: {}", e))?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Failed to write cassette: {}", e))
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<Interaction>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read cassette: {}", e))?;
+    parse(&contents)
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Interaction>, String> {
+    serde_json::from_str::<CassetteFile>(contents)
+        .map(|file| file.interactions)
+        .map_err(|e| format!("Failed to parse cassette: {}", e))
+}
+
+/// Credentials carried by the request: auth header values and a `key` query parameter.
+fn secrets_of(request: &reqwest::Request) -> Vec<String> {
+    let mut secrets: Vec<String> = ["authorization", "x-api-key", "api-key", "x-goog-api-key"]
+        .iter()
+        .filter_map(|name| request.headers().get(*name))
+        .filter_map(|value| value.to_str().ok())
+        .map(|value| value.trim_start_matches("Bearer ").to_string())
+        .collect();
+    secrets.extend(
+        request.url().query_pairs()
+            .filter(|(name, _)| name == "key")
+            .map(|(_, value)| value.to_string()),
+    );
+    secrets.retain(|secret| !secret.is_empty() && secret != REDACTED);
+    secrets
+}
+
+fn redact(text: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(text.to_string(), |text, secret| text.replace(secret.as_str(), REDACTED))
+}
+
+/// Mask key-like tokens in a response body. Providers echo part of a rejected key in
+/// their error (OpenAI: "Incorrect API key provided: sk-ab***...yz"), which matches no
+/// secret the request carried in full.
+fn redact_key_like(text: &str) -> String {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"\b(sk-[A-Za-z0-9_\-*]{2,}|AIza[A-Za-z0-9_\-*]{8,})").unwrap());
+    pattern.replace_all(text, REDACTED).to_string()
+}
+
+fn to_response(recorded: &RecordedResponse) -> reqwest::Response {
+    let mut builder = http::Response::builder().status(recorded.status);
+    if let Some(content_type) = &recorded.content_type {
+        builder = builder.header(http::header::CONTENT_TYPE, content_type.as_str());
+    }
+    let response = builder
+        .body(recorded.body.clone().into_bytes())
+        .unwrap_or_else(|_| http::Response::new(Vec::new()));
+    reqwest::Response::from(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interaction(body: &str, status: u16) -> Interaction {
+        Interaction {
+            request: RecordedRequest {
+                method: "POST".to_string(),
+                url: "https://api.example.com/v1/chat".to_string(),
+                body: Some(serde_json::json!({"prompt": "hi"})),
+            },
+            response: RecordedResponse { status, content_type: None, body: body.to_string() },
+        }
+    }
+
+    #[test]
+    fn test_redacts_headers_and_query_keys() {
+        let client = reqwest::Client::new();
+        let request = client.post("https://api.example.com/v1/models/x:generate?key=sk-query&alt=sse")
+            .header("Authorization", "Bearer sk-secret")
+            .body("{\"echo\":\"sk-secret\"}")
+            .build()
+            .unwrap();
+        let secrets = secrets_of(&request);
+        assert_eq!(secrets, vec!["sk-secret", "sk-query"]);
+        assert_eq!(redact(request.url().as_str(), &secrets), "https://api.example.com/v1/models/x:generate?key=REDACTED&alt=sse");
+        assert_eq!(redact("{\"echo\":\"sk-secret\"}", &secrets), "{\"echo\":\"REDACTED\"}");
+
+        let rejected = r#"{"error": {"message": "Incorrect API key provided: sk-ab*********************wxyz. You can find your API key at https://platform.openai.com/account/api-keys."}}"#;
+        assert_eq!(
+            redact_key_like(rejected),
+            r#"{"error": {"message": "Incorrect API key provided: REDACTED. You can find your API key at https://platform.openai.com/account/api-keys."}}"#
+        );
+        assert_eq!(redact_key_like("key AIzaSyA1b2C3d4E5f6 is invalid; ask about risk-free tasks"), "key REDACTED is invalid; ask about risk-free tasks");
+    }
+
+    #[tokio::test]
+    async fn test_replay_in_recorded_order() {
+        let client = HttpClient::replaying(vec![interaction("slow down", 429), interaction("ok", 200)]);
+        assert_eq!(client.api_key("openai").unwrap().as_deref(), Some(REDACTED));
+
+        let send = || client.send(client.post("https://api.example.com/v1/chat").json(&serde_json::json!({"prompt": "hi"})));
+        assert_eq!(send().await.unwrap().status().as_u16(), 429);
+        let second = send().await.unwrap();
+        assert_eq!(second.status().as_u16(), 200);
+        assert_eq!(second.text().await.unwrap(), "ok");
+        // Exhausted: the last match repeats.
+        assert_eq!(send().await.unwrap().status().as_u16(), 200);
+
+        let miss = client.send(client.post("https://api.example.com/v1/chat").json(&serde_json::json!({"prompt": "bye"}))).await;
+        assert!(miss.unwrap_err().starts_with("No recorded response for POST"));
+    }
+}
@@ -0,0 +1,431 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read};
+
+/// Largest file accepted, and the most any one part of a DOCX or PPTX may unpack to.
+pub const MAX_DOCUMENT_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    Pdf,
+    Docx,
+    Pptx,
+    Markdown,
+}
+
+impl DocumentFormat {
+    /// The format a file name's extension stands for.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let extension = name.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "pdf" => Some(Self::Pdf),
+            "docx" => Some(Self::Docx),
+            "pptx" => Some(Self::Pptx),
+            "md" | "markdown" | "txt" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+}
+
+/// A heading and the text under it; a slide or a page for decks and PDFs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    pub title: Option<String>,
+    pub text: String,
+}
+
+/// Text extracted from a document, split into sections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub file_name: String,
+    pub format: DocumentFormat,
+    pub sections: Vec<Section>,
+    /// The sections as one Markdown text, ready to be a prompt variable's value such as
+    /// `pitch_content` or `initial_idea`.
+    pub text: String,
+    pub word_count: usize,
+}
+
+/// Extract the text of `bytes`, a file called `file_name`. Nothing leaves the machine.
+pub fn extract(file_name: &str, bytes: &[u8]) -> Result<Document, String> {
+    let format = DocumentFormat::from_name(file_name)
+        .ok_or_else(|| format!("Unsupported document type: {}. Use a PDF, DOCX, PPTX or Markdown file.", file_name))?;
+    check_size(file_name, bytes.len() as u64)?;
+
+    let sections: Vec<Section> = match format {
+        DocumentFormat::Pdf => pdf_sections(bytes)?,
+        DocumentFormat::Docx => docx_sections(bytes)?,
+        DocumentFormat::Pptx => pptx_sections(bytes)?,
+        DocumentFormat::Markdown => markdown_sections(&String::from_utf8_lossy(bytes)),
+    }
+    .into_iter()
+    .map(|section| Section { title: section.title, text: normalize(&section.text) })
+    .filter(|section| section.title.is_some() || !section.text.is_empty())
+    .collect();
+
+    if sections.iter().all(|s| s.text.is_empty()) {
+        let hint = if format == DocumentFormat::Pdf {
+            " It may be scanned; attach the pages as images instead."
+        } else {
+            ""
+        };
+        return Err(format!("No text found in {}.{}", file_name, hint));
+    }
+
+    let word_count = sections.iter()
+        .flat_map(|s| s.title.iter().chain([&s.text]))
+        .map(|text| text.split_whitespace().count())
+        .sum();
+    Ok(Document {
+        file_name: file_name.to_string(),
+        format,
+        text: render(&sections),
+        sections,
+        word_count,
+    })
+}
+
+pub fn extract_file(path: &str) -> Result<Document, String> {
+    let file_name = std::path::Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().to_string());
+    // Checked before reading, so a huge file is never loaded.
+    let metadata = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    check_size(&file_name, metadata.len())?;
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    extract(&file_name, &bytes)
+}
+
+fn check_size(file_name: &str, len: u64) -> Result<(), String> {
+    if len > MAX_DOCUMENT_BYTES {
+        return Err(format!("{} is over the {} MB limit", file_name, MAX_DOCUMENT_BYTES / (1024 * 1024)));
+    }
+    Ok(())
+}
+
+fn render(sections: &[Section]) -> String {
+    sections.iter()
+        .map(|section| match &section.title {
+            Some(title) if section.text.is_empty() => format!("## {}", title),
+            Some(title) => format!("## {}\n\n{}", title, section.text),
+            None => section.text.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Unix line endings, no trailing spaces, and at most one blank line in a row.
+fn normalize(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").trim().to_string()
+}
+
+fn pdf_sections(bytes: &[u8]) -> Result<Vec<Section>, String> {
+    // The parser panics on some malformed files rather than returning an error.
+    let pages = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes))
+        .map_err(|_| "Failed to read PDF: the file is damaged or uses unsupported features".to_string())?
+        .map_err(|e| format!("Failed to read PDF: {}", e))?;
+    Ok(pages.into_iter()
+        .enumerate()
+        .map(|(i, text)| Section { title: Some(format!("Page {}", i + 1)), text })
+        .collect())
+}
+
+/// ATX headings (`# Title`) start sections; text before the first one has no title.
+fn markdown_sections(text: &str) -> Vec<Section> {
+    let mut sections = vec![Section { title: None, text: String::new() }];
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        let heading = line.trim_start_matches('#');
+        let level = line.len() - heading.len();
+        if !in_code && (1..=6).contains(&level) && heading.starts_with(' ') {
+            sections.push(Section { title: Some(heading.trim().trim_end_matches('#').trim().to_string()), text: String::new() });
+        } else {
+            let current = sections.last_mut().unwrap();
+            current.text.push_str(line);
+            current.text.push('\n');
+        }
+    }
+    sections
+}
+
+fn docx_sections(bytes: &[u8]) -> Result<Vec<Section>, String> {
+    let mut archive = open_archive(bytes, "DOCX")?;
+    let xml = read_part(&mut archive, "word/document.xml")?
+        .ok_or_else(|| "Failed to read DOCX: word/document.xml is missing".to_string())?;
+
+    let mut sections = vec![Section { title: None, text: String::new() }];
+    for paragraph in paragraphs(&xml)? {
+        let heading = paragraph.kind.as_deref().is_some_and(|style| {
+            style == "Title" || style.starts_with("Heading") || style == "outline"
+        });
+        if heading {
+            sections.push(Section { title: Some(paragraph.text), text: String::new() });
+        } else {
+            let current = sections.last_mut().unwrap();
+            current.text.push_str(&paragraph.text);
+            current.text.push('\n');
+        }
+    }
+    Ok(sections)
+}
+
+/// One section per slide, titled by its title placeholder, with the speaker notes
+/// (often the actual script of a pitch) after the slide's text.
+fn pptx_sections(bytes: &[u8]) -> Result<Vec<Section>, String> {
+    let mut archive = open_archive(bytes, "PPTX")?;
+    let mut slides: Vec<(u32, String)> = archive.file_names()
+        .filter_map(|name| {
+            let number = name.strip_prefix("ppt/slides/slide")?.strip_suffix(".xml")?.parse().ok()?;
+            Some((number, name.to_string()))
+        })
+        .collect();
+    slides.sort();
+
+    let mut sections = Vec::new();
+    for (number, name) in slides {
+        let xml = read_part(&mut archive, &name)?.unwrap_or_default();
+        let mut title = None;
+        let mut text = String::new();
+        for paragraph in paragraphs(&xml)? {
+            match paragraph.kind.as_deref() {
+                Some("title" | "ctrTitle") if title.is_none() => title = Some(paragraph.text),
+                _ => {
+                    text.push_str(&paragraph.text);
+                    text.push('\n');
+                }
+            }
+        }
+
+        let notes = match notes_part(&mut archive, number)? {
+            Some(notes) => read_part(&mut archive, &notes)?.unwrap_or_default(),
+            None => String::new(),
+        };
+        // Notes pages also hold the slide image and number; only the body is the notes.
+        let notes: Vec<String> = paragraphs(&notes)?
+            .into_iter()
+            .filter(|p| p.kind.as_deref() == Some("body"))
+            .map(|p| p.text)
+            .collect();
+        if !notes.is_empty() {
+            text.push_str(&format!("\nSpeaker notes:\n{}\n", notes.join("\n")));
+        }
+
+        let title = match title {
+            Some(title) => format!("Slide {}: {}", number, title),
+            None => format!("Slide {}", number),
+        };
+        sections.push(Section { title: Some(title), text });
+    }
+    Ok(sections)
+}
+
+/// The notes part of slide `number`, from the slide's relationships.
+fn notes_part(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, number: u32) -> Result<Option<String>, String> {
+    let Some(rels) = read_part(archive, &format!("ppt/slides/_rels/slide{}.xml.rels", number))? else {
+        return Ok(None);
+    };
+    let mut reader = Reader::from_str(&rels);
+    loop {
+        match reader.read_event().map_err(|e| format!("Failed to read PPTX: {}", e))? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                let is_notes = attribute(&e, b"Type").is_some_and(|t| t.ends_with("/notesSlide"));
+                if let Some(target) = attribute(&e, b"Target").filter(|_| is_notes) {
+                    return Ok(Some(format!("ppt/{}", target.trim_start_matches("../"))));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+fn open_archive<'a>(bytes: &'a [u8], kind: &str) -> Result<zip::ZipArchive<Cursor<&'a [u8]>>, String> {
+    zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("Failed to read {}: {}", kind, e))
+}
+
+/// A part of the archive as text, `None` if it doesn't exist.
+fn read_part(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<Option<String>, String> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", name, e)),
+    };
+    let mut xml = String::new();
+    file.take(MAX_DOCUMENT_BYTES)
+        .read_to_string(&mut xml)
+        .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    Ok(Some(xml))
+}
+
+/// A paragraph of an Office XML part with its kind: the paragraph style in a DOCX
+/// (`outline` for an outline level), the placeholder type of its shape in a PPTX.
+struct Paragraph {
+    text: String,
+    kind: Option<String>,
+}
+
+/// The non-empty paragraphs (`w:p`, `a:p`) of WordprocessingML or DrawingML.
+fn paragraphs(xml: &str) -> Result<Vec<Paragraph>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut paragraphs = Vec::new();
+    let mut current: Option<Paragraph> = None;
+    let mut shape_kind: Option<String> = None;
+    let mut in_text = false;
+    let mut in_properties = false;
+
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Failed to read document XML: {}", e))?;
+        let (start, empty) = match event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::Text(t) if in_text => {
+                if let Some(paragraph) = current.as_mut() {
+                    let text = t.unescape().map_err(|e| format!("Failed to read document XML: {}", e))?;
+                    paragraph.text.push_str(&text);
+                }
+                continue;
+            }
+            Event::End(e) => {
+                match e.local_name().as_ref() {
+                    b"t" => in_text = false,
+                    b"pPr" => in_properties = false,
+                    b"sp" => shape_kind = None,
+                    b"p" => {
+                        if let Some(paragraph) = current.take().filter(|p| !p.text.trim().is_empty()) {
+                            paragraphs.push(paragraph);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        match start.local_name().as_ref() {
+            b"sp" => shape_kind = None,
+            // A placeholder without a type is a body placeholder.
+            b"ph" => shape_kind = Some(attribute(&start, b"type").unwrap_or_else(|| "body".to_string())),
+            b"p" if !empty => current = Some(Paragraph { text: String::new(), kind: shape_kind.clone() }),
+            b"pPr" if !empty => in_properties = true,
+            b"pStyle" => {
+                if let Some(paragraph) = current.as_mut() {
+                    paragraph.kind = attribute(&start, b"val");
+                }
+            }
+            b"outlineLvl" => {
+                if let Some(paragraph) = current.as_mut() {
+                    paragraph.kind.get_or_insert_with(|| "outline".to_string());
+                }
+            }
+            b"t" if !empty => in_text = true,
+            // Tab stops are also `w:tab`, inside the paragraph properties.
+            b"tab" if !in_properties => push_text(&mut current, "\t"),
+            b"br" => push_text(&mut current, "\n"),
+            _ => {}
+        }
+    }
+    Ok(paragraphs)
+}
+
+fn push_text(current: &mut Option<Paragraph>, text: &str) {
+    if let Some(paragraph) = current.as_mut() {
+        paragraph.text.push_str(text);
+    }
+}
+
+/// Value of the attribute with local name `name`, whatever its namespace prefix.
+fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element.attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == name)
+        .and_then(|a| a.unescape_value().ok())
+        .map(|value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zip(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in parts {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_docx_and_markdown_sections() {
+        let document = r#"<w:document xmlns:w="w"><w:body>
+            <w:p><w:r><w:t>Draft for class</w:t></w:r></w:p>
+            <w:p><w:pPr><w:pStyle w:val="Heading1"/><w:tabs><w:tab w:val="left"/></w:tabs></w:pPr><w:r><w:t>Problem</w:t></w:r></w:p>
+            <w:p><w:r><w:t xml:space="preserve">Bakeries waste </w:t></w:r><w:r><w:t>30% &amp; more.</w:t></w:r></w:p>
+            <w:p/>
+            <w:p><w:r><w:t>Second</w:t><w:tab/><w:t>line</w:t></w:r></w:p>
+        </w:body></w:document>"#;
+        let docx = extract("plan.docx", &zip(&[("word/document.xml", document)])).unwrap();
+        assert_eq!(docx.sections, vec![
+            Section { title: None, text: "Draft for class".to_string() },
+            Section { title: Some("Problem".to_string()), text: "Bakeries waste 30% & more.\nSecond\tline".to_string() },
+        ]);
+        assert_eq!(docx.text, "Draft for class\n\n## Problem\n\nBakeries waste 30% & more.\nSecond\tline");
+
+        let markdown = extract("plan.md", b"# Problem  \r\n\r\n\r\nWaste.\n```\n# not a heading\n```\n## Solution ##\nApp.").unwrap();
+        assert_eq!(markdown.sections[0], Section { title: Some("Problem".to_string()), text: "Waste.\n```\n# not a heading\n```".to_string() });
+        assert_eq!(markdown.sections[1].title.as_deref(), Some("Solution"));
+        assert_eq!(markdown.word_count, 10);
+
+        assert!(extract("plan.odt", b"").unwrap_err().starts_with("Unsupported document type"));
+        assert!(extract("scan.pdf", b"not a pdf").unwrap_err().starts_with("Failed to read PDF"));
+    }
+
+    #[test]
+    fn test_pptx_slides_with_notes() {
+        let slide = |title: &str, body: &str| format!(
+            r#"<p:sld xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree>
+                <p:sp><p:nvSpPr><p:nvPr><p:ph type="title"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>{}</a:t></a:r></a:p></p:txBody></p:sp>
+                <p:sp><p:nvSpPr><p:nvPr><p:ph idx="1"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>{}</a:t></a:r></a:p></p:txBody></p:sp>
+            </p:spTree></p:cSld></p:sld>"#,
+            title, body
+        );
+        let notes = r#"<p:notes xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree>
+            <p:sp><p:nvSpPr><p:nvPr><p:ph type="sldNum"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>10</a:t></a:r></a:p></p:txBody></p:sp>
+            <p:sp><p:nvSpPr><p:nvPr><p:ph type="body"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>Pause here.</a:t></a:r></a:p></p:txBody></p:sp>
+        </p:spTree></p:cSld></p:notes>"#;
+        let rels = r#"<Relationships><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/notesSlide" Target="../notesSlides/notesSlide1.xml"/></Relationships>"#;
+        let (first, tenth) = (slide("Crumbs", "Fresh bread, less waste"), slide("Ask", "$200k"));
+        let deck = zip(&[
+            ("ppt/slides/slide10.xml", tenth.as_str()),
+            ("ppt/slides/slide1.xml", first.as_str()),
+            ("ppt/slides/_rels/slide10.xml.rels", rels),
+            ("ppt/notesSlides/notesSlide1.xml", notes),
+        ]);
+
+        let document = extract("Deck.PPTX", &deck).unwrap();
+        assert_eq!(document.format, DocumentFormat::Pptx);
+        assert_eq!(document.sections[0], Section { title: Some("Slide 1: Crumbs".to_string()), text: "Fresh bread, less waste".to_string() });
+        assert_eq!(document.sections[1], Section { title: Some("Slide 10: Ask".to_string()), text: "$200k\n\nSpeaker notes:\nPause here.".to_string() });
+
+        let path = std::env::temp_dir().join(format!("venturelab-deck-{}.pptx", uuid::Uuid::new_v4()));
+        std::fs::File::create(&path).unwrap().set_len(MAX_DOCUMENT_BYTES + 1).unwrap();
+        let error = extract_file(&path.to_string_lossy()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.ends_with("is over the 50 MB limit"), "{}", error);
+    }
+}
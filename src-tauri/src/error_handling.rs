@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::time::Duration;
 
+/// A failed provider call, as returned by `AIProvider` and the AI commands. Serialized
+/// as-is to the frontend, which should branch on `code` rather than on `message`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct APIError {
     pub error_type: APIErrorType,
+    /// Stable identifier, e.g. "rate_limit" or "schema_mismatch". Defaults to the
+    /// error type's code; some failures use a more specific one.
+    pub code: String,
     pub message: String,
     pub provider: String,
     pub status_code: Option<u16>,
@@ -19,10 +25,59 @@ pub enum APIErrorType {
     NetworkError,
     ServerError,
     InvalidRequest,
+    /// Stopped by the user; never retried or handed to a fallback provider.
+    Cancelled,
     Unknown,
 }
 
+impl APIErrorType {
+    pub fn code(&self) -> &'static str {
+        match self {
+            APIErrorType::RateLimit => "rate_limit",
+            APIErrorType::QuotaExceeded => "quota_exceeded",
+            APIErrorType::InvalidApiKey => "invalid_api_key",
+            APIErrorType::ModelNotFound => "model_not_found",
+            APIErrorType::NetworkError => "network_error",
+            APIErrorType::ServerError => "server_error",
+            APIErrorType::InvalidRequest => "invalid_request",
+            APIErrorType::Cancelled => "cancelled",
+            APIErrorType::Unknown => "unknown",
+        }
+    }
+
+    /// Whether a request that ended with this error (after any retries) should move on to
+    /// the next provider in the fallback chain. Bad input or a missing model would fail
+    /// the same way elsewhere, so those are surfaced directly.
+    pub fn allows_fallback(&self) -> bool {
+        matches!(
+            self,
+            APIErrorType::RateLimit
+                | APIErrorType::ServerError
+                | APIErrorType::NetworkError
+                | APIErrorType::InvalidApiKey
+                | APIErrorType::QuotaExceeded
+        )
+    }
+}
+
 impl APIError {
+    pub fn new(error_type: APIErrorType, provider: &str, message: impl Into<String>) -> Self {
+        APIError {
+            code: error_type.code().to_string(),
+            error_type,
+            message: message.into(),
+            provider: provider.to_string(),
+            status_code: None,
+            retry_after: None,
+        }
+    }
+
+    /// Replace the default code with a more specific one.
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = code.to_string();
+        self
+    }
+
     pub fn from_status_code(status: u16, provider: &str, body: Option<&str>) -> Self {
         let error_type = match status {
             429 => APIErrorType::RateLimit,
@@ -52,11 +107,9 @@ impl APIError {
         };
 
         APIError {
-            error_type,
-            message,
-            provider: provider.to_string(),
             status_code: Some(status),
             retry_after,
+            ..APIError::new(error_type, provider, message)
         }
     }
 
@@ -64,13 +117,22 @@ impl APIError {
         // Raw transport errors are useful for debugging but must never reach the UI;
         // log them here instead of stashing them on the struct.
         eprintln!("{} network error: {}", provider, error);
-        APIError {
-            error_type: APIErrorType::NetworkError,
-            message: format!("Network error: Unable to connect to {}. Please check your internet connection.", provider),
-            provider: provider.to_string(),
-            status_code: None,
-            retry_after: None,
-        }
+        APIError::new(
+            APIErrorType::NetworkError,
+            provider,
+            format!("Network error: Unable to connect to {}. Please check your internet connection.", provider),
+        )
+    }
+
+    /// Something the provider's response didn't prepare us for, such as a body that isn't
+    /// the expected JSON. The detail is logged; the user gets a generic message.
+    pub fn unexpected(provider: &str, detail: &str) -> Self {
+        eprintln!("Unexpected {} error: {}", provider, detail);
+        APIError::new(APIErrorType::Unknown, provider, "An unexpected error occurred. See the application logs.")
+    }
+
+    pub fn cancelled(provider: &str) -> Self {
+        APIError::new(APIErrorType::Cancelled, provider, "Generation cancelled")
     }
 
     pub fn should_retry(&self) -> bool {
@@ -105,6 +167,9 @@ impl APIError {
             APIErrorType::QuotaExceeded => {
                 format!("📊 {} quota exceeded. Please check your account billing or usage limits.", self.provider)
             },
+            APIErrorType::InvalidApiKey if self.code == "api_key_missing" => {
+                format!("🔑 No {} API key configured. Add one in Settings.", self.provider)
+            },
             APIErrorType::InvalidApiKey => {
                 format!("🔑 Invalid {} API key. Please check your settings.", self.provider)
             },
@@ -117,21 +182,52 @@ impl APIError {
             APIErrorType::ServerError => {
                 format!("⚠️ {} is experiencing issues. Please try again later.", self.provider)
             },
+            APIErrorType::InvalidRequest if self.code == "context_length_exceeded" || self.code == "input_too_long" => {
+                format!("📏 {}", self.message)
+            },
+            APIErrorType::InvalidRequest if self.code == "schema_mismatch" => {
+                format!("🧩 {}", self.message)
+            },
+            APIErrorType::InvalidRequest if self.code == "invalid_override" || self.code == "no_targets" => {
+                format!("⚙️ {}", self.message)
+            },
+            APIErrorType::InvalidRequest if self.code == "invalid_image" || self.code == "vision_unsupported" => {
+                format!("🖼️ {}", self.message)
+            },
+            APIErrorType::InvalidRequest if matches!(self.code.as_str(), "unknown_function" | "tools_unsupported" | "tool_rounds_exceeded") => {
+                format!("🧰 {}", self.message)
+            },
             APIErrorType::InvalidRequest => {
                 "❌ Invalid request. Please check your input and try again.".to_string()
             },
-            APIErrorType::Unknown => {
+            APIErrorType::Cancelled | APIErrorType::Unknown => {
                 self.message.clone()
             },
         }
     }
 }
 
+impl fmt::Display for APIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.provider, self.code, self.message)
+    }
+}
+
+impl std::error::Error for APIError {}
+
+/// Failures of our own (loading settings, reading files) in commands that otherwise
+/// return provider errors.
+impl From<String> for APIError {
+    fn from(message: String) -> Self {
+        APIError::new(APIErrorType::Unknown, "", message)
+    }
+}
+
 // Extract retry-after information from provider-specific error responses
 fn extract_retry_after(provider: &str, body: Option<&str>) -> Option<u64> {
     if let Some(body) = body {
         match provider {
-            "openai" => {
+            "openai" | "openai_compatible" | "azure" => {
                 // OpenAI includes retry_after in error response
                 if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
                     json["error"]["retry_after"].as_u64()
@@ -155,7 +251,7 @@ fn extract_retry_after(provider: &str, body: Option<&str>) -> Option<u64> {
 }
 
 // Note: RetryPolicy struct and its methods were removed as they were not being used.
-// The retry logic is currently implemented directly in generate_ai_response_v2 in main.rs
+// The retry logic is implemented in generate_with_backoff in main.rs
 
 // Helper function to parse error responses from different providers
 pub fn parse_provider_error(provider: &str, status: u16, body: &str) -> APIError {
@@ -163,7 +259,7 @@ pub fn parse_provider_error(provider: &str, status: u16, body: &str) -> APIError
     
     // Try to extract more specific error messages
     match provider {
-        "openai" => {
+        "openai" | "openai_compatible" | "azure" => {
             if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
                 if let Some(message) = json["error"]["message"].as_str() {
                     error.message = message.to_string();
@@ -182,10 +278,69 @@ pub fn parse_provider_error(provider: &str, status: u16, body: &str) -> APIError
                 if let Some(message) = json["error"]["message"].as_str() {
                     error.message = message.to_string();
                 }
+                // A bad key is a 400 (reason API_KEY_INVALID) or, for a key that may not
+                // use this API, a 403 PERMISSION_DENIED; either way another provider may work.
+                let key_reason = json["error"]["details"].as_array().is_some_and(|details| {
+                    details.iter().any(|d| d["reason"].as_str().is_some_and(|r| r.starts_with("API_KEY_")))
+                });
+                let key_denied = json["error"]["status"] == "PERMISSION_DENIED" && error.message.to_lowercase().contains("api key");
+                if key_reason || key_denied {
+                    error.error_type = APIErrorType::InvalidApiKey;
+                    error.code = APIErrorType::InvalidApiKey.code().to_string();
+                }
             }
         },
         _ => {}
     }
     
     error
-}
\ No newline at end of file
+}
+
+/// An error reported inside a stream that started with a 200, like Anthropic's `error`
+/// event (`{"type": "overloaded_error", "message": ...}`) or Ollama's `error` string,
+/// typed as if the provider had answered with the matching status.
+pub fn parse_stream_error(provider: &str, event_error: &serde_json::Value) -> APIError {
+    let status = match event_error["type"].as_str() {
+        Some("rate_limit_error") => 429,
+        Some("authentication_error") => 401,
+        Some("permission_error") => 403,
+        Some("not_found_error") => 404,
+        Some("invalid_request_error") | Some("request_too_large") => 400,
+        Some("overloaded_error") => 529,
+        _ => 500,
+    };
+    let mut error = APIError::from_status_code(status, provider, None);
+    if let Some(message) = event_error["message"].as_str().or(event_error.as_str()) {
+        error.message = message.to_string();
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_and_messages() {
+        let rate_limited = parse_provider_error("openai", 429, r#"{"error": {"message": "Slow down", "retry_after": 7}}"#);
+        assert_eq!(rate_limited.code, "rate_limit");
+        assert_eq!(rate_limited.retry_after, Some(7));
+        assert_eq!(rate_limited.to_string(), "openai rate_limit: Slow down");
+
+        let missing_key = APIError::new(APIErrorType::InvalidApiKey, "gemini", "API key not configured").with_code("api_key_missing");
+        assert_eq!(missing_key.to_user_message(), "🔑 No gemini API key configured. Add one in Settings.");
+
+        let cancelled = APIError::cancelled("mock");
+        assert!(!cancelled.should_retry() && !cancelled.error_type.allows_fallback());
+        assert_eq!(serde_json::to_value(&cancelled).unwrap()["code"], "cancelled");
+
+        let blocked = parse_provider_error("gemini", 403, r#"{"error": {"code": 403, "message": "Method doesn't allow unregistered callers (callers without established identity). Please use API Key or other form of API consumer identity to call this API.", "status": "PERMISSION_DENIED"}}"#);
+        assert_eq!(blocked.code, "invalid_api_key");
+
+        let overloaded = parse_stream_error("anthropic", &serde_json::json!({"type": "overloaded_error", "message": "Overloaded"}));
+        assert_eq!((overloaded.error_type, overloaded.message.as_str()), (APIErrorType::ServerError, "Overloaded"));
+        assert_eq!(parse_stream_error("anthropic", &serde_json::json!({"type": "rate_limit_error"})).error_type, APIErrorType::RateLimit);
+        assert_eq!(parse_stream_error("anthropic", &serde_json::json!({"type": "invalid_request_error"})).error_type, APIErrorType::InvalidRequest);
+        assert_eq!(parse_stream_error("ollama", &serde_json::json!("out of memory")).message, "out of memory");
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::ai_providers::{AIRequest, AIResponse, ChatMessage, ChatRole};
+
+/// Most model turns of one generation. The last one may not call functions, so the model
+/// has to answer with what it has.
+pub const MAX_TOOL_ROUNDS: u32 = 5;
+
+/// A function the model may call during a generation ("tools" in the providers' APIs, not
+/// the app's tools like Idea Forge). Functions are implemented in Rust below; the model
+/// only sees their definitions, and `generate_with_tools` in `main.rs` runs the calls.
+/// Only `name` needs to be sent; the rest is filled in from the registry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON Schema of the arguments object.
+    #[serde(default)]
+    pub parameters: Value,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// The model decides whether to call a function.
+    #[default]
+    Auto,
+    /// The model must answer in text, though functions stay defined for the history.
+    None,
+}
+
+/// A function call the model asked for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// The provider's id for the call, or one made up for providers without ids.
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// A call that was run, reported in `AIResponse::tool_runs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolRun {
+    #[serde(flatten)]
+    pub call: ToolCall,
+    pub output: Value,
+    /// `output` is an error message the model was shown instead of a result.
+    pub is_error: bool,
+}
+
+/// Every registered function.
+pub fn registry() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "market_size".to_string(),
+            description: "Bottom-up market sizing: total addressable market (customers times annual spend), \
+                the serviceable share of it, and the share obtainable in the first years."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "customers": {"type": "number", "description": "Potential customers in the whole market"},
+                    "annual_spend": {"type": "number", "description": "Yearly spend per customer on this kind of product"},
+                    "serviceable_share": {"type": "number", "description": "Fraction of the market the business can reach, 0 to 1"},
+                    "obtainable_share": {"type": "number", "description": "Fraction of the serviceable market it can win, 0 to 1"},
+                    "currency": {"type": "string", "description": "Currency of annual_spend, e.g. USD"}
+                },
+                "required": ["customers", "annual_spend", "serviceable_share", "obtainable_share"]
+            }),
+        },
+        ToolDefinition {
+            name: "country_profile".to_string(),
+            description: "Population, GDP per capita, currency and languages of a country, from a bundled \
+                dataset of major markets (approximate 2023 figures)."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "country": {"type": "string", "description": "Country name or ISO 3166 alpha-2 code"}
+                },
+                "required": ["country"]
+            }),
+        },
+    ]
+}
+
+/// Replace each definition with the registered one of the same name; functions have to
+/// be implemented here to be offered.
+pub fn resolve(definitions: &[ToolDefinition]) -> Result<Vec<ToolDefinition>, String> {
+    let registry = registry();
+    definitions.iter()
+        .map(|definition| registry.iter()
+            .find(|registered| registered.name == definition.name)
+            .cloned()
+            .ok_or_else(|| format!(
+                "Unknown function: {}. Available: {}",
+                definition.name,
+                registry.iter().map(|r| r.name.as_str()).collect::<Vec<_>>().join(", ")
+            )))
+        .collect()
+}
+
+/// Run `call`. A failure is reported to the model rather than ending the generation, so
+/// it can correct its arguments.
+pub fn run(call: &ToolCall) -> ToolRun {
+    let result = match call.name.as_str() {
+        "market_size" => market_size(&call.arguments),
+        "country_profile" => country_profile(&call.arguments),
+        other => Err(format!("Unknown function: {}", other)),
+    };
+    let (output, is_error) = match result {
+        Ok(output) => (output, false),
+        Err(message) => (json!({"error": message}), true),
+    };
+    ToolRun { call: call.clone(), output, is_error }
+}
+
+/// The request for the model's next turn: the conversation so far, the turn in which it
+/// called functions, and the results.
+pub fn follow_up(request: &AIRequest, response: &AIResponse, runs: &[ToolRun]) -> AIRequest {
+    let mut messages = request.conversation();
+    messages.push(ChatMessage {
+        role: ChatRole::Assistant,
+        content: response.content.clone(),
+        tool_calls: response.tool_calls.clone(),
+    });
+    messages.extend(runs.iter().map(|run| ChatMessage {
+        role: ChatRole::Tool,
+        content: run.output.to_string(),
+        tool_calls: vec![run.call.clone()],
+    }));
+
+    AIRequest {
+        prompt: String::new(),
+        messages,
+        ..request.clone()
+    }
+}
+
+fn number(arguments: &Value, name: &str) -> Result<f64, String> {
+    arguments[name].as_f64().ok_or_else(|| format!("{} must be a number", name))
+}
+
+fn share(arguments: &Value, name: &str) -> Result<f64, String> {
+    let value = number(arguments, name)?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{} must be between 0 and 1, got {}", name, value));
+    }
+    Ok(value)
+}
+
+fn market_size(arguments: &Value) -> Result<Value, String> {
+    let customers = number(arguments, "customers")?;
+    let annual_spend = number(arguments, "annual_spend")?;
+    if customers < 0.0 || annual_spend < 0.0 {
+        return Err("customers and annual_spend can't be negative".to_string());
+    }
+    let tam = customers * annual_spend;
+    let sam = tam * share(arguments, "serviceable_share")?;
+    let som = sam * share(arguments, "obtainable_share")?;
+    Ok(json!({
+        "tam": tam.round(),
+        "sam": sam.round(),
+        "som": som.round(),
+        "currency": arguments["currency"].as_str().unwrap_or("USD"),
+    }))
+}
+
+/// Bundled with the app so Global Compass has figures to work from offline.
+const COUNTRIES: &str = include_str!("../data/countries.json");
+
+fn country_profile(arguments: &Value) -> Result<Value, String> {
+    let query = arguments["country"].as_str().ok_or("country must be a string")?.trim().to_lowercase();
+    let countries: Vec<Value> = serde_json::from_str(COUNTRIES).map_err(|e| format!("Failed to read country data: {}", e))?;
+    countries.into_iter()
+        .find(|country| {
+            country["code"].as_str().is_some_and(|code| code.to_lowercase() == query)
+                || country["name"].as_str().is_some_and(|name| name.to_lowercase() == query)
+        })
+        .ok_or_else(|| format!("No data for {}; the dataset covers about 30 major markets", query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall { id: "call_1".to_string(), name: name.to_string(), arguments }
+    }
+
+    #[test]
+    fn test_registered_functions() {
+        let sized = run(&call("market_size", json!({
+            "customers": 2_000_000, "annual_spend": 120, "serviceable_share": 0.25, "obtainable_share": 0.1
+        })));
+        assert!(!sized.is_error);
+        assert_eq!(sized.output, json!({"tam": 240_000_000.0, "sam": 60_000_000.0, "som": 6_000_000.0, "currency": "USD"}));
+        let invalid = run(&call("market_size", json!({"customers": 10, "annual_spend": 5, "serviceable_share": 25, "obtainable_share": 0.1})));
+        assert!(invalid.is_error);
+        assert_eq!(invalid.output["error"], "serviceable_share must be between 0 and 1, got 25");
+
+        assert_eq!(run(&call("country_profile", json!({"country": "de"}))).output["name"], "Germany");
+        assert_eq!(run(&call("country_profile", json!({"country": "Kenya"}))).output["currency"], "KES");
+        assert!(run(&call("country_profile", json!({"country": "Atlantis"}))).is_error);
+        assert!(run(&call("launch_rocket", json!({}))).is_error);
+
+        let requested = [ToolDefinition { name: "country_profile".to_string(), description: String::new(), parameters: Value::Null }];
+        assert_eq!(resolve(&requested).unwrap()[0].parameters["required"], json!(["country"]));
+        assert!(resolve(&[ToolDefinition { name: "launch_rocket".to_string(), ..requested[0].clone() }])
+            .unwrap_err()
+            .contains("Available: market_size, country_profile"));
+    }
+}
@@ -0,0 +1,135 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+
+/// Per image. Anthropic's limit, the lowest of the providers (OpenAI and Gemini take 20 MB).
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// An image sent with the prompt, e.g. a pitch deck slide: a local file, or base64 data
+/// (a `data:` URL works too). `resolve` turns either into data with a checked MIME type,
+/// which is all the providers see.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageInput {
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub data: Option<String>,
+    /// Optional: the type is detected from the content, which is what gets sent.
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
+impl ImageInput {
+    /// MIME type of a resolved image.
+    pub fn media_type(&self) -> &str {
+        self.mime_type.as_deref().unwrap_or("image/png")
+    }
+
+    /// Base64 data of a resolved image.
+    pub fn base64(&self) -> &str {
+        self.data.as_deref().unwrap_or_default()
+    }
+
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type(), self.base64())
+    }
+}
+
+/// Load files and check every image's format and size. Reads files, so call it off the
+/// async runtime.
+pub fn resolve(images: &[ImageInput]) -> Result<Vec<ImageInput>, String> {
+    images.iter().enumerate().map(|(i, image)| resolve_one(image).map_err(|e| format!("Image {}: {}", i + 1, e))).collect()
+}
+
+fn resolve_one(image: &ImageInput) -> Result<ImageInput, String> {
+    let bytes = match (&image.path, &image.data) {
+        (_, Some(data)) => decode_data(data).map_err(|e| format!("Failed to decode image data: {}", e))?,
+        (Some(path), None) => {
+            // Checked before reading, so a huge file is never loaded.
+            let metadata = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            check_size(metadata.len())?;
+            std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?
+        }
+        (None, None) => return Err("No path or data given".to_string()),
+    };
+
+    check_size(bytes.len() as u64)?;
+    let mime_type = sniff(&bytes)
+        .ok_or_else(|| "Unsupported format; use PNG, JPEG, GIF or WebP.".to_string())?;
+
+    Ok(ImageInput { path: None, data: Some(STANDARD.encode(&bytes)), mime_type: Some(mime_type.to_string()) })
+}
+
+fn check_size(len: u64) -> Result<(), String> {
+    if len > MAX_IMAGE_BYTES as u64 {
+        return Err(format!(
+            "{:.1} MB is over the {} MB limit. Resize or compress it.",
+            len as f64 / (1024.0 * 1024.0),
+            MAX_IMAGE_BYTES / (1024 * 1024)
+        ));
+    }
+    Ok(())
+}
+
+/// Bytes of base64 `data`, which may also be a `data:` URL as a browser's FileReader makes.
+pub fn decode_data(data: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    let data = data.split_once(";base64,").map_or(data, |(_, data)| data);
+    STANDARD.decode(data.trim())
+}
+
+/// The image format from its magic bytes; only formats every vision provider accepts.
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Stands in for the images in cache keys, which would otherwise hold megabytes of base64.
+pub fn fingerprint(images: &[ImageInput]) -> Vec<String> {
+    images.iter()
+        .map(|image| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            image.data.hash(&mut hasher);
+            image.path.hash(&mut hasher);
+            format!("{}:{:016x}", image.media_type(), hasher.finish())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+    #[test]
+    fn test_resolve_files_and_data() {
+        let path = std::env::temp_dir().join(format!("venturelab-slide-{}.png", uuid::Uuid::new_v4()));
+        std::fs::write(&path, PNG).unwrap();
+        let from_file = ImageInput { path: Some(path.to_string_lossy().to_string()), data: None, mime_type: None };
+        let from_url = ImageInput { path: None, data: Some(format!("data:image/png;base64,{}", STANDARD.encode(PNG))), mime_type: None };
+
+        let resolved = resolve(&[from_file, from_url]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resolved[0], resolved[1]);
+        assert_eq!(resolved[0].media_type(), "image/png");
+        assert!(resolved[0].data_url().starts_with("data:image/png;base64,iVBORw0KGgo"));
+        assert_eq!(fingerprint(&resolved[..1]), fingerprint(&resolved[1..]));
+
+        let text = ImageInput { path: None, data: Some(STANDARD.encode("not an image")), mime_type: Some("image/png".to_string()) };
+        assert!(resolve(&[text]).unwrap_err().starts_with("Image 1: Unsupported format"));
+        let missing = ImageInput { path: Some("/nonexistent/slide.png".to_string()), data: None, mime_type: None };
+        assert!(resolve(&[missing]).unwrap_err().contains("Failed to read"));
+
+        let large = std::env::temp_dir().join(format!("venturelab-slide-{}.png", uuid::Uuid::new_v4()));
+        std::fs::File::create(&large).unwrap().set_len(MAX_IMAGE_BYTES as u64 + 1).unwrap();
+        let oversized = ImageInput { path: Some(large.to_string_lossy().to_string()), data: None, mime_type: None };
+        let error = resolve(&[oversized]).unwrap_err();
+        std::fs::remove_file(&large).unwrap();
+        assert!(error.contains("over the 5 MB limit"), "{}", error);
+    }
+}
@@ -146,6 +146,69 @@ pub fn validate_ollama_key(key: &str) -> ValidationResult {
     }
 }
 
+/// Validates an OpenAI-compatible server key
+/// Expected format: optional bearer token (any non-whitespace format)
+pub fn validate_openai_compatible_key(key: &str) -> ValidationResult {
+    let trimmed_key = key.trim();
+    
+    // Local servers (LM Studio, llama.cpp) usually need no key; hosted gateways such as
+    // OpenRouter or a vLLM deployment issue tokens in their own formats.
+    if trimmed_key.is_empty() {
+        return ValidationResult {
+            is_valid: true,
+            error_message: None,
+            masked_key: "(no authentication)".to_string(),
+        };
+    }
+    
+    let is_valid = !trimmed_key.contains(char::is_whitespace);
+    
+    let error_message = if !is_valid {
+        Some("Bearer token contains whitespace characters".to_string())
+    } else {
+        None
+    };
+    
+    ValidationResult {
+        is_valid,
+        error_message,
+        masked_key: mask_api_key(trimmed_key),
+    }
+}
+
+/// Validates Azure OpenAI resource key format
+/// Expected format: alphanumeric string, 32 characters (classic) or longer (newer resources)
+pub fn validate_azure_key(key: &str) -> ValidationResult {
+    let trimmed_key = key.trim();
+    
+    if trimmed_key.is_empty() {
+        return ValidationResult {
+            is_valid: false,
+            error_message: Some("API key cannot be empty".to_string()),
+            masked_key: String::new(),
+        };
+    }
+    
+    let pattern = Regex::new(r"^[a-zA-Z0-9]+$").unwrap();
+    let is_valid = pattern.is_match(trimmed_key) && trimmed_key.len() >= 32;
+    
+    let error_message = if !is_valid {
+        if trimmed_key.len() < 32 {
+            Some("Azure OpenAI key seems too short (minimum 32 characters)".to_string())
+        } else {
+            Some("Azure OpenAI key must be alphanumeric".to_string())
+        }
+    } else {
+        None
+    };
+    
+    ValidationResult {
+        is_valid,
+        error_message,
+        masked_key: mask_api_key(trimmed_key),
+    }
+}
+
 /// Masks an API key for secure display
 /// Shows first 7 and last 4 characters, with asterisks in between
 pub fn mask_api_key(key: &str) -> String {
@@ -184,6 +247,8 @@ pub fn validate_api_key(provider: &str, key: &str) -> ValidationResult {
         "anthropic" => validate_anthropic_key(key),
         "gemini" => validate_gemini_key(key),
         "ollama" => validate_ollama_key(key),
+        "openai_compatible" => validate_openai_compatible_key(key),
+        "azure" => validate_azure_key(key),
         _ => ValidationResult {
             is_valid: false,
             error_message: Some(format!("Unknown provider: {}", provider)),
@@ -297,6 +362,41 @@ mod tests {
         assert!(result.error_message.unwrap().contains("whitespace"));
     }
 
+    #[test]
+    fn test_openai_compatible_validation() {
+        // Valid - no auth
+        let result = validate_openai_compatible_key("");
+        assert!(result.is_valid);
+        assert_eq!(result.masked_key, "(no authentication)");
+        
+        // Valid - arbitrary token formats, including short ones
+        assert!(validate_openai_compatible_key("sk-or-v1-abcdef0123456789").is_valid);
+        assert!(validate_openai_compatible_key("token").is_valid);
+        assert!(validate_api_key("openai_compatible", "lm-studio").is_valid);
+        
+        // Invalid - contains whitespace
+        let result = validate_openai_compatible_key("bearer token with spaces");
+        assert!(!result.is_valid);
+        assert!(result.error_message.unwrap().contains("whitespace"));
+    }
+
+    #[test]
+    fn test_azure_validation() {
+        // Valid - classic 32-character hex key
+        assert!(validate_azure_key("0123456789abcdef0123456789abcdef").is_valid);
+        assert!(validate_api_key("azure", "0123456789ABCDEF0123456789ABCDEF0123").is_valid);
+        
+        // Invalid - too short
+        let result = validate_azure_key("0123456789abcdef");
+        assert!(!result.is_valid);
+        assert!(result.error_message.unwrap().contains("too short"));
+        
+        // Invalid - non-alphanumeric
+        let result = validate_azure_key("0123456789abcdef-0123456789abcdef");
+        assert!(!result.is_valid);
+        assert!(result.error_message.unwrap().contains("alphanumeric"));
+    }
+
     #[test]
     fn test_mask_api_key() {
         // Standard key
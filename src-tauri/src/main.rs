@@ -1,4 +1,4 @@
-use tauri::{command, Manager};
+use tauri::{command, Emitter, Manager};
 use serde::{Deserialize, Serialize};
 use reqwest;
 use tokio;
@@ -10,67 +10,68 @@ mod prompts;
 mod usage_tracking;
 mod error_handling;
 mod key_validation;
+mod cancellation;
+mod model_capabilities;
+mod structured_output;
+mod tool_results;
+mod response_cache;
+mod mock_provider;
+mod cassettes;
+mod token_estimation;
+mod map_reduce;
+mod candidates;
+mod images;
+mod documents;
+mod function_calling;
 
 use settings::{AppSettings, AIProviderConfig};
-use ai_providers::{AIRequest, AIResponse, create_provider, AIProvider};
+use ai_providers::{AIRequest, AIResponse, FallbackAttempt, StreamChunk, DeltaSink, create_provider, AIProvider, ProviderEnum};
 use secure_storage::{ApiKeyInfo};
 use prompts::{PromptCollection, ToolPrompt};
+use cancellation::{CancellationRegistry, CancelToken};
+use tool_results::{ToolResponse, ToolResult};
+use error_handling::{APIError, APIErrorType};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GenerateRequest {
     pub prompt: String,
     pub temperature: f32,
     pub tool_type: String,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub system_message: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GenerateResponse {
     pub content: String,
     pub provider: String,
+    pub model: String,
     pub success: bool,
     pub error: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct OpenAIRequest {
-    model: String,
-    messages: Vec<OpenAIMessage>,
-    temperature: f32,
-    max_tokens: u32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OpenAIMessage {
-    role: String,
-    content: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OpenAIResponse {
-    choices: Vec<OpenAIChoice>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OpenAIChoice {
-    message: OpenAIMessage,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OllamaRequest {
-    model: String,
-    prompt: String,
-    stream: bool,
-    options: OllamaOptions,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OllamaOptions {
-    temperature: f32,
+/// One column of a `compare_providers` run: a configured provider, optionally with a
+/// different model than the one in its settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompareTarget {
+    pub provider_type: String,
+    pub model: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct OllamaResponse {
-    response: String,
+/// Result of one `compare_providers` leg, also emitted as an `ai-compare-result` event
+/// as soon as that leg finishes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComparisonLeg {
+    pub request_id: Option<String>,
+    /// Position of the target in the request.
+    pub index: usize,
+    pub provider: String,
+    pub model: String,
+    pub response: AIResponse,
+    /// Wall-clock time including retries.
+    pub latency_ms: u64,
 }
 
 #[command]
@@ -180,6 +181,13 @@ async fn test_connection(provider: &AIProviderConfig) -> Result<ConnectionTestRe
         "anthropic" => test_anthropic_connection(&client, provider).await,
         "gemini" => test_gemini_connection(&client, provider).await,
         "ollama" => test_ollama_connection(&client, provider).await,
+        "openai_compatible" => test_openai_compatible_connection(&client, provider).await,
+        "azure" => test_azure_connection(&client, provider).await,
+        "mock" => Ok(ConnectionTestResult {
+            success: true,
+            error: None,
+            models: Some(vec![provider.model.clone()]),
+        }),
         _ => Ok(ConnectionTestResult {
             success: false,
             error: Some("Unsupported provider type".to_string()),
@@ -392,6 +400,104 @@ async fn test_ollama_connection(
     }
 }
 
+async fn test_openai_compatible_connection(
+    client: &reqwest::Client,
+    provider: &AIProviderConfig,
+) -> Result<ConnectionTestResult, String> {
+    let name = provider.display_name.as_deref().unwrap_or("OpenAI-compatible server");
+    let url = format!("{}/models", provider.base_url);
+    
+    // The key is optional: local servers usually run without one
+    let mut request = client.get(&url);
+    if let Ok(Some(api_key)) = secure_storage::retrieve_api_key(&provider.provider_type) {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+    
+    match request.send().await {
+        Ok(resp) if resp.status().is_success() => {
+            let models = extract_openai_models(resp).await.unwrap_or_default();
+            Ok(ConnectionTestResult {
+                success: true,
+                error: None,
+                models: Some(models),
+            })
+        }
+        Ok(resp) => {
+            let status = resp.status();
+            let error_text = resp.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            Ok(ConnectionTestResult {
+                success: false,
+                error: Some(format!("{} responded with status {}: {}", name, status, error_text)),
+                models: None,
+            })
+        }
+        Err(e) => Ok(ConnectionTestResult {
+            success: false,
+            error: Some(format!("Connection to {} failed: {}", name, e)),
+            models: None,
+        }),
+    }
+}
+
+async fn test_azure_connection(
+    client: &reqwest::Client,
+    provider: &AIProviderConfig,
+) -> Result<ConnectionTestResult, String> {
+    let api_key = match secure_storage::retrieve_api_key(&provider.provider_type) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Ok(ConnectionTestResult {
+            success: false,
+            error: Some("API key is required for Azure OpenAI".to_string()),
+            models: None,
+        }),
+        Err(e) => return Ok(ConnectionTestResult {
+            success: false,
+            error: Some(format!("Failed to retrieve API key: {}", e)),
+            models: None,
+        }),
+    };
+
+    // Data-plane keys cannot list deployments, so probe the configured one with a one-token completion
+    let deployment = provider.deployment.as_deref().filter(|d| !d.is_empty()).unwrap_or(&provider.model);
+    let url = format!(
+        "{}/openai/deployments/{}/chat/completions?api-version={}",
+        provider.base_url.trim_end_matches('/'),
+        deployment,
+        provider.api_version.as_deref().unwrap_or(settings::DEFAULT_AZURE_API_VERSION)
+    );
+    let response = client
+        .post(&url)
+        .header("api-key", api_key)
+        .json(&serde_json::json!({
+            "messages": [{"role": "user", "content": "ping"}],
+            "max_tokens": 1
+        }))
+        .send()
+        .await;
+
+    match response {
+        Ok(resp) if resp.status().is_success() => Ok(ConnectionTestResult {
+            success: true,
+            error: None,
+            models: Some(vec![deployment.to_string()]),
+        }),
+        Ok(resp) => {
+            let status = resp.status();
+            let error_text = resp.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            Ok(ConnectionTestResult {
+                success: false,
+                error: Some(format!("Azure OpenAI deployment '{}' responded with status {}: {}", deployment, status, error_text)),
+                models: None,
+            })
+        }
+        Err(e) => Ok(ConnectionTestResult {
+            success: false,
+            error: Some(format!("Connection failed: {}", e)),
+            models: None,
+        }),
+    }
+}
+
 async fn extract_openai_models(response: reqwest::Response) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let json: serde_json::Value = response.json().await?;
     let models = json["data"]
@@ -428,82 +534,590 @@ async fn extract_ollama_models(response: reqwest::Response) -> Result<Vec<String
     Ok(models)
 }
 
+/// `request_id` is optional; pass one to be able to stop the call with `cancel_generation`.
 #[command]
 async fn generate_ai_response_v2(
     request: AIRequest,
+    request_id: Option<String>,
     app_handle: tauri::AppHandle,
-) -> Result<AIResponse, String> {
-    let start_time = std::time::Instant::now();
-    
-    let settings = settings::load_settings(&app_handle)
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<AIResponse, APIError> {
+    let cancel = request_id.as_deref().map(|id| registry.register(id));
+    let response = run_generation(request, &app_handle, None, cancel).await;
+    if let Some(id) = &request_id {
+        registry.remove(id);
+    }
+    response
+}
+
+/// Abort an in-flight generation (including a retry backoff). Returns false if nothing
+/// with this id is running.
+#[command]
+async fn cancel_generation(
+    request_id: String,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<bool, String> {
+    Ok(registry.cancel(&request_id))
+}
+
+/// Streams the generation as `ai-stream-chunk` events tagged with `request_id`. The
+/// final event has `done: true` and the token usage; the full response is also returned.
+#[command]
+async fn generate_ai_response_stream(
+    request: AIRequest,
+    request_id: String,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<AIResponse, APIError> {
+    let emitter = app_handle.clone();
+    let chunk_request_id = request_id.clone();
+    let on_delta = move |delta: &str| {
+        let chunk = StreamChunk {
+            request_id: chunk_request_id.clone(),
+            delta: delta.to_string(),
+            done: false,
+            success: true,
+            error: None,
+            usage: None,
+        };
+        if let Err(e) = emitter.emit("ai-stream-chunk", chunk) {
+            eprintln!("Failed to emit stream chunk: {}", e);
+        }
+    };
+
+    let cancel = registry.register(&request_id);
+    let response = run_generation(request, &app_handle, Some(&on_delta), Some(cancel)).await;
+    registry.remove(&request_id);
+
+    // Listeners wait for `done`, including when the request fails before any provider call.
+    let final_chunk = match &response {
+        Ok(response) => StreamChunk {
+            request_id,
+            delta: String::new(),
+            done: true,
+            success: response.success,
+            error: response.error.clone(),
+            usage: response.usage.clone(),
+        },
+        Err(e) => StreamChunk {
+            request_id,
+            delta: String::new(),
+            done: true,
+            success: false,
+            error: Some(e.to_user_message()),
+            usage: None,
+        },
+    };
+    if let Err(e) = app_handle.emit("ai-stream-chunk", final_chunk) {
+        eprintln!("Failed to emit final stream chunk: {}", e);
+    }
+
+    response
+}
+
+/// Generates Idea Forge ideas as structured data.
+#[command]
+async fn generate_business_ideas(
+    request: AIRequest,
+    request_id: Option<String>,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<ToolResponse<tool_results::IdeaForgeResult>, APIError> {
+    generate_tool_result(request, request_id, &app_handle, &registry).await
+}
+
+/// Generates a Global Compass market analysis as structured data.
+#[command]
+async fn analyze_market(
+    request: AIRequest,
+    request_id: Option<String>,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<ToolResponse<tool_results::MarketAnalysis>, APIError> {
+    generate_tool_result(request, request_id, &app_handle, &registry).await
+}
+
+/// Generates a Pitch Perfect scorecard as structured data.
+#[command]
+async fn score_pitch(
+    request: AIRequest,
+    request_id: Option<String>,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<ToolResponse<tool_results::PitchScorecard>, APIError> {
+    generate_tool_result(request, request_id, &app_handle, &registry).await
+}
+
+/// Generates a PRD as structured sections. `response.content` is replaced with the
+/// rendered markdown, which is what the PRD view displays and exports.
+#[command]
+async fn generate_prd(
+    request: AIRequest,
+    request_id: Option<String>,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<ToolResponse<tool_results::PrdDocument>, APIError> {
+    let mut output: ToolResponse<tool_results::PrdDocument> =
+        generate_tool_result(request, request_id, &app_handle, &registry).await?;
+    if let Some(prd) = &output.result {
+        output.response.content = prd.to_markdown();
+    }
+    Ok(output)
+}
+
+/// How many times a tool command asks the model to fix output it couldn't parse.
+const MAX_REPAIR_ATTEMPTS: u32 = 2;
+
+/// Requests `T` as JSON and parses it. Output that doesn't parse or match the schema is
+/// sent back to the model with the problem, up to `MAX_REPAIR_ATTEMPTS` times.
+async fn generate_tool_result<T: ToolResult>(
+    request: AIRequest,
+    request_id: Option<String>,
+    app_handle: &tauri::AppHandle,
+    registry: &CancellationRegistry,
+) -> Result<ToolResponse<T>, APIError> {
+    let mut request = tool_results::prepare_request::<T>(request);
+    let mut repair_attempts = 0;
+    // One registration for all attempts, so a cancel between them still stops the repair.
+    let cancel = request_id.as_deref().map(|id| registry.register(id));
+
+    let outcome = loop {
+        let response = match run_generation(request.clone(), app_handle, None, cancel.clone()).await {
+            Ok(response) => response,
+            Err(e) => break Err(e),
+        };
+
+        match tool_results::parse::<T>(&response) {
+            Ok(result) => break Ok(ToolResponse { result: Some(result), response, repair_attempts }),
+            Err(problem) if repair_attempts < MAX_REPAIR_ATTEMPTS && tool_results::is_repairable(&response) => {
+                eprintln!("{} output unusable ({}), asking the model to repair it", T::TOOL_ID, problem);
+                repair_attempts += 1;
+                request = tool_results::repair_request(&request, &response, &problem);
+            }
+            Err(_) => break Ok(ToolResponse { result: None, response, repair_attempts }),
+        }
+    };
+
+    if let Some(id) = &request_id {
+        registry.remove(id);
+    }
+    outcome
+}
+
+/// Shared generation path: walks the provider chain (the preferred provider, then the
+/// configured fallbacks), retrying retryable errors on each and recording usage per
+/// attempt. Text is streamed to `on_delta` when one is given, and the call (or the
+/// backoff between retries) is abandoned as soon as `cancel` fires.
+async fn run_generation(
+    mut request: AIRequest,
+    app_handle: &tauri::AppHandle,
+    on_delta: Option<DeltaSink<'_>>,
+    mut cancel: Option<CancelToken>,
+) -> Result<AIResponse, APIError> {
+    let settings = settings::load_settings(app_handle)
         .await
         .map_err(|e| format!("Failed to load settings: {}", e))?;
     
-    let provider_config = settings.get_active_provider().clone();
-    let provider_type = provider_config.provider_type.clone();
-    let model = provider_config.model.clone();
-    let provider = create_provider(provider_config.clone());
-    
-    // Get the tool type from the request context
-    let tool_type = request.context.as_ref()
+    let tool_type = tool_type_of(&request);
+    apply_tool_route(&mut request, &settings, &tool_type);
+    let provider = request.provider.clone().unwrap_or_else(|| settings.preferred_provider.clone());
+    resolve_images(&mut request, &provider).await?;
+    request.tools = function_calling::resolve(&request.tools).map_err(|e| {
+        APIError::new(APIErrorType::InvalidRequest, request.provider.as_deref().unwrap_or(&settings.preferred_provider), e)
+            .with_code("unknown_function")
+    })?;
+    if candidates::ranking(&request) == candidates::Ranking::Model && candidates::count(&request) > 1 {
+        // Links the judging call's usage record to the generation's.
+        usage_tracking::tag(&mut request, &uuid::Uuid::new_v4().to_string(), "candidates");
+    }
+    let chain = settings.provider_chain_for(request.provider.as_deref(), request.model.as_deref())
+        .map_err(|e| {
+            APIError::new(APIErrorType::InvalidRequest, request.provider.as_deref().unwrap_or(&settings.preferred_provider), e)
+                .with_code("invalid_override")
+        })?;
+    let mut fallbacks = Vec::new();
+    let overrides = model_capabilities::load_overrides(app_handle).await.unwrap_or_else(|e| {
+        eprintln!("Failed to load model capability overrides: {}", e);
+        Default::default()
+    });
+
+    for (index, provider_config) in chain.iter().enumerate() {
+        let capabilities = capabilities_of(provider_config, &overrides).await;
+        let estimate = token_estimation::estimate(&request, &provider_config.provider_type, &provider_config.model, &capabilities);
+        if let Some(warning) = &estimate.warning {
+            eprintln!("{} {}: {}", provider_config.provider_type, provider_config.model, warning);
+        }
+        let split = map_reduce::should_split(&request, &estimate, &settings.map_reduce, &tool_type);
+        let guarded = if split { Ok(()) } else { estimate.guard(&provider_config.provider_type, &provider_config.model) };
+        let guarded = guarded.and_then(|()| check_support(&request, provider_config, &capabilities));
+        if let Err(e) = guarded {
+            // A fallback may have a larger context window, or the features missing here.
+            if index + 1 < chain.len() {
+                fallbacks.push(FallbackAttempt {
+                    provider: provider_config.provider_type.clone(),
+                    model: provider_config.model.clone(),
+                    error_type: Some(e.error_type.clone()),
+                    reason: e.to_user_message(),
+                });
+                continue;
+            }
+            let mut response = AIResponse::from_error(&provider_config.model, e);
+            response.fallbacks = fallbacks;
+            return Ok(response);
+        }
+
+        let cache_key = (settings.cache.enabled && !request.bypass_cache)
+            .then(|| response_cache::cache_key(&request, provider_config));
+        if let Some(mut hit) = cache_key.as_deref().and_then(|key| cached_response(key, &settings.cache, &tool_type, app_handle)) {
+            if let Some(on_delta) = on_delta {
+                on_delta(&hit.content);
+            }
+            hit.fallbacks = fallbacks;
+            return Ok(hit);
+        }
+
+        let (mut response, status) = if split {
+            generate_map_reduce(&request, provider_config, &capabilities, &settings.map_reduce, app_handle, on_delta, &mut cancel).await
+        } else {
+            generate_with_tools(
+                &request,
+                (*provider_config).clone(),
+                &tool_type,
+                app_handle,
+                on_delta,
+                &mut cancel,
+            ).await
+        };
+
+        // Only fall back before anything was streamed, otherwise the UI would show the
+        // start of one provider's answer glued to another's.
+        let can_fall_back = status == usage_tracking::UsageStatus::Failed
+            && index + 1 < chain.len()
+            && response.content.is_empty()
+            && response.error_type.as_ref().is_some_and(|t| t.allows_fallback());
+
+        if can_fall_back {
+            eprintln!("{} failed ({}), falling back to {}",
+                response.provider,
+                response.error.as_deref().unwrap_or("unknown error"),
+                chain[index + 1].provider_type);
+            fallbacks.push(FallbackAttempt {
+                provider: response.provider,
+                model: response.model,
+                error_type: response.error_type,
+                reason: response.error.unwrap_or_default(),
+            });
+            continue;
+        }
+
+        if status == usage_tracking::UsageStatus::Success && candidates::count(&request) > 1 {
+            if response.candidates.len() > 1 {
+                rank_candidates(&mut response, &request, provider_config, &tool_type, app_handle, &mut cancel).await;
+            }
+            // Candidates aren't streamed, so send the answer now, also when fewer than asked
+            // for came back. With functions, `generate_with_tools` has sent it already.
+            if let (Some(on_delta), true) = (on_delta, request.tools.is_empty()) {
+                on_delta(&response.content);
+            }
+        }
+
+        if let (Some(key), usage_tracking::UsageStatus::Success) = (&cache_key, status) {
+            if let Err(e) = response_cache::store(app_handle, key, &response, &settings.cache) {
+                eprintln!("Failed to cache response: {}", e);
+            }
+        }
+
+        response.fallbacks = fallbacks;
+        return Ok(response);
+    }
+
+    Err(APIError::new(APIErrorType::InvalidRequest, "", "No AI provider is configured").with_code("no_provider"))
+}
+
+/// What `provider_config`'s model can do, with the user's overrides applied. Bundled
+/// limits, except for Ollama: its window is the local num_ctx, and asking the local server
+/// is cheap where a hosted provider would cost a round trip per request.
+async fn capabilities_of(
+    provider_config: &AIProviderConfig,
+    overrides: &model_capabilities::CapabilityOverrides,
+) -> model_capabilities::ModelCapabilities {
+    let mut capabilities = if provider_config.provider_type == "ollama" {
+        create_provider(provider_config.clone()).capabilities().await
+    } else {
+        model_capabilities::lookup(&provider_config.provider_type, &provider_config.model)
+    };
+    if let Some(o) = overrides.get(&model_capabilities::override_key(&provider_config.provider_type, &provider_config.model)) {
+        capabilities.apply(o);
+    }
+    capabilities
+}
+
+/// Refuse images or functions for a model known not to support them; most would ignore
+/// them silently and answer without. Unknown models get the benefit of the doubt.
+fn check_support(request: &AIRequest, provider_config: &AIProviderConfig, capabilities: &model_capabilities::ModelCapabilities) -> Result<(), APIError> {
+    if capabilities.source == model_capabilities::CapabilitySource::Default {
+        return Ok(());
+    }
+    let unsupported = |message: &str, code: &str| Err(APIError::new(
+        APIErrorType::InvalidRequest,
+        &provider_config.provider_type,
+        format!("{} {}", provider_config.model, message),
+    ).with_code(code));
+    if !request.images.is_empty() && !capabilities.vision {
+        return unsupported("can't read images. Choose a model with vision support or remove the images.", "vision_unsupported");
+    }
+    if !request.tools.is_empty() && !capabilities.tool_calling {
+        return unsupported("can't call functions. Choose a model with tool calling support.", "tools_unsupported");
+    }
+    Ok(())
+}
+
+/// Load and check the request's images off the async runtime, since they may be files.
+async fn resolve_images(request: &mut AIRequest, provider: &str) -> Result<(), APIError> {
+    if request.images.is_empty() {
+        return Ok(());
+    }
+    let inputs = std::mem::take(&mut request.images);
+    request.images = tokio::task::spawn_blocking(move || images::resolve(&inputs))
+        .await
+        .map_err(|e| format!("Failed to load images: {}", e))?
+        .map_err(|e| APIError::new(APIErrorType::InvalidRequest, provider, e).with_code("invalid_image"))?;
+    Ok(())
+}
+
+/// Fill in what `request` leaves to its tool's route in settings. A model routed for one
+/// provider is not applied when the request picks another. A route to a provider that
+/// isn't enabled is skipped, leaving the global preference, rather than failing the way
+/// an explicit override would.
+fn apply_tool_route(request: &mut AIRequest, settings: &AppSettings, tool_type: &str) {
+    let Some(route) = settings.tool_routing.get(tool_type) else {
+        return;
+    };
+    let available = route.provider.as_deref().is_none_or(|provider_type| {
+        settings.get_provider(provider_type)
+            .is_some_and(|config| config.enabled || config.provider_type == settings.preferred_provider)
+    });
+    if available {
+        if request.model.is_none() && (request.provider.is_none() || request.provider == route.provider) {
+            request.model = route.model.clone();
+        }
+        if request.provider.is_none() {
+            request.provider = route.provider.clone();
+        }
+    } else {
+        eprintln!("{} is routed to {:?}, which is not enabled; using the preferred provider", tool_type, route.provider);
+    }
+    request.temperature = request.temperature.or(route.temperature);
+    request.max_tokens = request.max_tokens.or(route.max_tokens);
+}
+
+/// The tool a request came from, as tagged in its context for usage tracking.
+fn tool_type_of(request: &AIRequest) -> String {
+    request.context.as_ref()
         .and_then(|ctx| ctx.get("tool"))
         .cloned()
-        .unwrap_or_else(|| "unknown".to_string());
-    
-    // Attempt to generate response with retry logic
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Sends the same request to each target concurrently and returns every leg in target
+/// order. Legs don't wait on each other: each is emitted as `ai-compare-result` when it
+/// finishes, and each is retried and recorded in usage on its own. Fallbacks and the
+/// response cache are skipped, since the point is to see each provider's live answer.
+#[command]
+async fn compare_providers(
+    request: AIRequest,
+    targets: Vec<CompareTarget>,
+    request_id: Option<String>,
+    app_handle: tauri::AppHandle,
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> Result<Vec<ComparisonLeg>, APIError> {
+    if targets.is_empty() {
+        return Err(APIError::new(APIErrorType::InvalidRequest, "", "Choose at least one provider to compare").with_code("no_targets"));
+    }
+
+    let settings = settings::load_settings(&app_handle)
+        .await
+        .map_err(|e| format!("Failed to load settings: {}", e))?;
+
+    // The same checks as a request's own provider override: known, enabled, safe URL.
+    let mut configs = Vec::new();
+    for target in &targets {
+        let mut chain = settings.provider_chain_for(Some(&target.provider_type), target.model.as_deref())
+            .map_err(|e| APIError::new(APIErrorType::InvalidRequest, &target.provider_type, e).with_code("invalid_override"))?;
+        configs.push(chain.swap_remove(0));
+    }
+
+    // Resolved once for all legs; every target has to handle what the request needs.
+    let mut request = request;
+    resolve_images(&mut request, &targets[0].provider_type).await?;
+    request.tools = function_calling::resolve(&request.tools).map_err(|e| {
+        APIError::new(APIErrorType::InvalidRequest, &targets[0].provider_type, e).with_code("unknown_function")
+    })?;
+    let overrides = model_capabilities::load_overrides(&app_handle).await.unwrap_or_else(|e| {
+        eprintln!("Failed to load model capability overrides: {}", e);
+        Default::default()
+    });
+    for config in &configs {
+        check_support(&request, config, &capabilities_of(config, &overrides).await)?;
+    }
+
+    let tool_type = tool_type_of(&request);
+    let cancel = request_id.as_deref().map(|id| registry.register(id));
+    let mut legs = tokio::task::JoinSet::new();
+
+    for (index, config) in configs.into_iter().enumerate() {
+        let request = request.clone();
+        let tool_type = tool_type.clone();
+        let request_id = request_id.clone();
+        let app_handle = app_handle.clone();
+        let mut cancel = cancel.clone();
+
+        legs.spawn(async move {
+            let start_time = std::time::Instant::now();
+            let provider = config.provider_type.clone();
+            let model = config.model.clone();
+            let (response, _) = generate_with_tools(&request, config, &tool_type, &app_handle, None, &mut cancel).await;
+
+            let leg = ComparisonLeg {
+                request_id,
+                index,
+                provider,
+                model,
+                response,
+                latency_ms: start_time.elapsed().as_millis() as u64,
+            };
+            if let Err(e) = app_handle.emit("ai-compare-result", leg.clone()) {
+                eprintln!("Failed to emit comparison result: {}", e);
+            }
+            leg
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(joined) = legs.join_next().await {
+        match joined {
+            Ok(leg) => results.push(leg),
+            Err(e) => eprintln!("Comparison leg failed to complete: {}", e),
+        }
+    }
+    results.sort_by_key(|leg| leg.index);
+
+    if let Some(id) = &request_id {
+        registry.remove(id);
+    }
+    Ok(results)
+}
+
+/// Serve `key` from the response cache, recording the hit as a zero-cost request that
+/// saved the tokens of the original generation. Cache errors count as a miss.
+fn cached_response(
+    key: &str,
+    cache: &settings::CacheSettings,
+    tool_type: &str,
+    app_handle: &tauri::AppHandle,
+) -> Option<AIResponse> {
+    let start_time = std::time::Instant::now();
+    let hit = match response_cache::lookup(app_handle, key, cache) {
+        Ok(hit) => hit?,
+        Err(e) => {
+            eprintln!("Failed to read response cache: {}", e);
+            return None;
+        }
+    };
+
+    let usage_record = usage_tracking::UsageRecord {
+        id: None,
+        timestamp: chrono::Utc::now(),
+        provider: hit.provider.clone(),
+        model: hit.model.clone(),
+        tool: tool_type.to_string(),
+        input_tokens: 0,
+        output_tokens: 0,
+        total_tokens: 0,
+        success: true,
+        error_message: None,
+        response_time_ms: start_time.elapsed().as_millis() as i64,
+        status: usage_tracking::UsageStatus::Cached,
+        saved_tokens: hit.usage.as_ref().map(|u| u.total_tokens as i32).unwrap_or(0),
+        group_id: None,
+        stage: None,
+    };
+
+    let app_handle_clone = app_handle.clone();
+    tokio::spawn(async move {
+        if let Err(e) = usage_tracking::record_usage(&app_handle_clone, usage_record).await {
+            eprintln!("Failed to record usage: {}", e);
+        }
+    });
+
+    Some(hit)
+}
+
+/// Call `provider`, retrying retryable failures up to three times with the delay the
+/// error asks for. `None` means the request was cancelled; dropping the provider future
+/// aborts the HTTP call.
+async fn generate_with_backoff(
+    provider: &ProviderEnum,
+    request: &AIRequest,
+    on_delta: Option<DeltaSink<'_>>,
+    cancel: &mut Option<CancelToken>,
+) -> Option<Result<AIResponse, APIError>> {
     let mut attempt = 0;
     let max_retries = 3;
-    
-    let response = loop {
-        match provider.generate(&request).await {
-            Ok(resp) => break Ok(resp),
-            Err(e) => {
-                // Check if this is a retryable error
-                if let Ok(api_error) = serde_json::from_str::<error_handling::APIError>(&e) {
-                    if api_error.should_retry() && attempt < max_retries {
-                        attempt += 1;
-                        let delay = api_error.get_retry_delay();
-                        eprintln!("Attempt {} failed with retryable error: {}. Retrying in {:?}...", 
-                            attempt, api_error.to_user_message(), delay);
-                        tokio::time::sleep(delay).await;
-                        continue;
-                    }
+
+    loop {
+        let result = tokio::select! {
+            result = async {
+                match on_delta {
+                    Some(on_delta) => provider.generate_stream(request, on_delta).await,
+                    None => provider.generate(request).await,
+                }
+            } => result,
+            _ = cancellation::cancelled(cancel) => break None,
+        };
+        match result {
+            Ok(resp) => break Some(Ok(resp)),
+            Err(e) if e.should_retry() && attempt < max_retries => {
+                attempt += 1;
+                let delay = e.get_retry_delay();
+                eprintln!("Attempt {} failed with retryable error: {}. Retrying in {:?}...", 
+                    attempt, e, delay);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => continue,
+                    _ = cancellation::cancelled(cancel) => break None,
                 }
-                break Err(e);
             }
+            Err(e) => break Some(Err(e)),
         }
-    };
+    }
+}
+
+/// One provider's share of `run_generation`: the retry loop plus usage recording.
+async fn generate_with_retries(
+    request: &AIRequest,
+    provider_config: AIProviderConfig,
+    tool_type: &str,
+    app_handle: &tauri::AppHandle,
+    on_delta: Option<DeltaSink<'_>>,
+    cancel: &mut Option<CancelToken>,
+) -> (AIResponse, usage_tracking::UsageStatus) {
+    let start_time = std::time::Instant::now();
+    let provider_type = provider_config.provider_type.clone();
+    let model = provider_config.model.clone();
+    let provider = create_provider(provider_config);
+    let response = generate_with_backoff(&provider, request, on_delta, cancel).await;
     
     let response_time_ms = start_time.elapsed().as_millis() as i64;
+    let status = match &response {
+        None => usage_tracking::UsageStatus::Cancelled,
+        Some(Ok(resp)) if resp.success => usage_tracking::UsageStatus::Success,
+        Some(_) => usage_tracking::UsageStatus::Failed,
+    };
     
-    // Convert retry error to AIResponse format if needed
     let final_response = match response {
-        Ok(resp) => resp,
-        Err(e) => {
-            // Check if the error is an API error that we can parse
-            if let Ok(api_error) = serde_json::from_str::<error_handling::APIError>(&e) {
-                AIResponse {
-                    content: String::new(),
-                    provider: provider_type.clone(),
-                    model: model.clone(),
-                    success: false,
-                    error: Some(api_error.to_user_message()),
-                    usage: None,
-                }
-            } else {
-                // Error string isn't a structured APIError (e.g. a raw transport failure).
-                // Never surface raw internals to the UI; log for debugging.
-                eprintln!("Unhandled AI error ({} {}): {}", provider_type, model, e);
-                AIResponse {
-                    content: String::new(),
-                    provider: provider_type.clone(),
-                    model: model.clone(),
-                    success: false,
-                    error: Some("An unexpected error occurred. See the application logs.".to_string()),
-                    usage: None,
-                }
-            }
-        }
+        None => AIResponse::from_error(&model, APIError::cancelled(&provider_type)),
+        Some(Ok(resp)) => resp,
+        Some(Err(e)) => AIResponse::from_error(&model, e),
     };
     
     // Record usage asynchronously (don't fail the request if recording fails)
@@ -511,14 +1125,18 @@ async fn generate_ai_response_v2(
         id: None,
         timestamp: chrono::Utc::now(),
         provider: provider_type,
-        model: model,
-        tool: tool_type,
+        model,
+        tool: tool_type.to_string(),
         input_tokens: final_response.usage.as_ref().map(|u| u.prompt_tokens as i32).unwrap_or(0),
         output_tokens: final_response.usage.as_ref().map(|u| u.completion_tokens as i32).unwrap_or(0),
         total_tokens: final_response.usage.as_ref().map(|u| u.total_tokens as i32).unwrap_or(0),
         success: final_response.success,
         error_message: final_response.error.clone(),
         response_time_ms,
+        status,
+        saved_tokens: 0,
+        group_id: request.context.as_ref().and_then(|c| c.get(usage_tracking::USAGE_GROUP_KEY)).cloned(),
+        stage: request.context.as_ref().and_then(|c| c.get(usage_tracking::USAGE_STAGE_KEY)).cloned(),
     };
     
     // Record usage in background
@@ -529,14 +1147,202 @@ async fn generate_ai_response_v2(
         }
     });
     
-    Ok(final_response)
+    (final_response, status)
+}
+
+/// `generate_with_retries`, then, while the model calls functions, running the calls and
+/// sending the results back for another turn. The last of `MAX_TOOL_ROUNDS` turns has to
+/// answer. Each turn is recorded under one usage group, and the returned usage is the
+/// total. The answer is passed to `on_delta` whole at the end, since only the last turn's
+/// text is the answer. Without functions this is a single `generate_with_retries`.
+async fn generate_with_tools(
+    request: &AIRequest,
+    provider_config: AIProviderConfig,
+    tool_type: &str,
+    app_handle: &tauri::AppHandle,
+    on_delta: Option<DeltaSink<'_>>,
+    cancel: &mut Option<CancelToken>,
+) -> (AIResponse, usage_tracking::UsageStatus) {
+    if request.tools.is_empty() {
+        return generate_with_retries(request, provider_config, tool_type, app_handle, on_delta, cancel).await;
+    }
+
+    let context = request.context.as_ref();
+    let group = context.and_then(|c| c.get(usage_tracking::USAGE_GROUP_KEY)).cloned()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let stage = context.and_then(|c| c.get(usage_tracking::USAGE_STAGE_KEY)).map_or("turn".to_string(), |s| format!("{} turn", s));
+    let mut usage = ai_providers::TokenUsage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 };
+    let mut usage_reported = false;
+    let mut runs = Vec::new();
+    let mut turn = request.clone();
+    let mut round = 1;
+    loop {
+        let last = round == function_calling::MAX_TOOL_ROUNDS;
+        if last {
+            turn.tool_choice = function_calling::ToolChoice::None;
+        }
+        usage_tracking::tag(&mut turn, &group, &format!("{} {}", stage, round));
+        let (mut response, mut status) = generate_with_retries(&turn, provider_config.clone(), tool_type, app_handle, None, cancel).await;
+        if let Some(u) = &response.usage {
+            usage.prompt_tokens += u.prompt_tokens;
+            usage.completion_tokens += u.completion_tokens;
+            usage.total_tokens += u.total_tokens;
+            usage_reported = true;
+        }
+
+        let succeeded = status == usage_tracking::UsageStatus::Success;
+        if succeeded && !response.tool_calls.is_empty() && !last {
+            let results: Vec<function_calling::ToolRun> = response.tool_calls.iter().map(function_calling::run).collect();
+            turn = function_calling::follow_up(&turn, &response, &results);
+            runs.extend(results);
+            round += 1;
+            continue;
+        }
+
+        // Ollama has no tool choice, so a model may keep calling functions regardless.
+        if succeeded && response.content.trim().is_empty() && !response.tool_calls.is_empty() {
+            let error = APIError::new(
+                APIErrorType::InvalidRequest,
+                &response.provider,
+                format!("The model was still calling functions after {} turns instead of answering.", round),
+            ).with_code("tool_rounds_exceeded");
+            response = AIResponse::from_error(&response.model, error);
+            status = usage_tracking::UsageStatus::Failed;
+        }
+        // All turns were billed, including when the last one is turned into an error.
+        if usage_reported {
+            response.usage = Some(usage);
+        }
+        response.tool_runs = runs;
+        if let (Some(on_delta), usage_tracking::UsageStatus::Success) = (on_delta, status) {
+            on_delta(&response.content);
+        }
+        return (response, status);
+    }
+}
+
+/// `generate_with_retries` for an input too long for the model: the split variable is
+/// condensed part by part (repeatedly, if the notes are still too long), then the tool's
+/// template is sent with the notes. Each call is recorded under one usage group, and
+/// the returned usage is the total. Only the final pass is streamed.
+async fn generate_map_reduce(
+    request: &AIRequest,
+    provider_config: &AIProviderConfig,
+    capabilities: &model_capabilities::ModelCapabilities,
+    settings: &settings::MapReduceSettings,
+    app_handle: &tauri::AppHandle,
+    on_delta: Option<DeltaSink<'_>>,
+    cancel: &mut Option<CancelToken>,
+) -> (AIResponse, usage_tracking::UsageStatus) {
+    let tool_type = tool_type_of(request);
+    let (provider_type, model) = (provider_config.provider_type.as_str(), provider_config.model.as_str());
+    let failed = |message: String| (
+        AIResponse::from_error(model, APIError::new(APIErrorType::InvalidRequest, provider_type, message).with_code("input_too_long")),
+        usage_tracking::UsageStatus::Failed,
+    );
+    let Some(split) = request.split_input.as_ref() else {
+        return failed("Nothing to split".to_string());
+    };
+
+    let group = uuid::Uuid::new_v4().to_string();
+    let instruction = map_reduce::instruction_for(settings, &tool_type);
+    let method = token_estimation::method_for(provider_type, model);
+    let Some(budget) = map_reduce::chunk_budget(request, split, &instruction, provider_type, model, capabilities) else {
+        return failed(format!("{}'s context window is too small to analyze this input in parts. Choose a model with a larger context window.", model));
+    };
+
+    let mut text = split.variables.get(&split.variable).cloned().unwrap_or_default();
+    let mut usage = ai_providers::TokenUsage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 };
+    let mut round = 1;
+    loop {
+        let parts = map_reduce::split(&text, budget, method);
+        if parts.len() > settings.max_chunks as usize {
+            return failed(format!(
+                "This input would need {} parts to fit {}, more than the limit of {}. Shorten it or choose a model with a larger context window.",
+                parts.len(), model, settings.max_chunks
+            ));
+        }
+
+        let mut notes = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let mut map = map_reduce::map_request(request, split, &instruction, part, i + 1, parts.len(), map_reduce::map_output_tokens(capabilities.context_window));
+            let stage = if round == 1 { format!("map {}/{}", i + 1, parts.len()) } else { format!("map {}.{}/{}", round, i + 1, parts.len()) };
+            usage_tracking::tag(&mut map, &group, &stage);
+            let (response, status) = generate_with_retries(&map, provider_config.clone(), &tool_type, app_handle, None, cancel).await;
+            if status != usage_tracking::UsageStatus::Success {
+                return (response, status);
+            }
+            if let Some(u) = &response.usage {
+                usage.prompt_tokens += u.prompt_tokens;
+                usage.completion_tokens += u.completion_tokens;
+                usage.total_tokens += u.total_tokens;
+            }
+            notes.push(response.content);
+        }
+        text = map_reduce::combine(&notes);
+
+        let mut reduce = map_reduce::reduce_request(request, split, &text);
+        let estimate = token_estimation::estimate(&reduce, provider_type, model, capabilities);
+        if estimate.fits || round == map_reduce::MAX_ROUNDS {
+            if let Err(e) = estimate.guard(provider_type, model) {
+                return (AIResponse::from_error(model, e.with_code("input_too_long")), usage_tracking::UsageStatus::Failed);
+            }
+            usage_tracking::tag(&mut reduce, &group, "reduce");
+            let (mut response, status) = generate_with_tools(&reduce, provider_config.clone(), &tool_type, app_handle, on_delta, cancel).await;
+            if let Some(u) = &mut response.usage {
+                u.prompt_tokens += usage.prompt_tokens;
+                u.completion_tokens += usage.completion_tokens;
+                u.total_tokens += usage.total_tokens;
+            }
+            return (response, status);
+        }
+        round += 1;
+    }
+}
+
+/// Order `response.candidates` best first as the request asks, making the best one the
+/// answer. A model that fails to judge them is replaced by the heuristic.
+async fn rank_candidates(
+    response: &mut AIResponse,
+    request: &AIRequest,
+    provider_config: &AIProviderConfig,
+    tool_type: &str,
+    app_handle: &tauri::AppHandle,
+    cancel: &mut Option<CancelToken>,
+) {
+    let scores = match candidates::ranking(request) {
+        candidates::Ranking::None => return,
+        candidates::Ranking::Heuristic => candidates::heuristic_scores(&response.candidates),
+        candidates::Ranking::Model => {
+            let mut judge = candidates::ranking_request(request, &response.candidates);
+            if let Some(group) = request.context.as_ref().and_then(|c| c.get(usage_tracking::USAGE_GROUP_KEY)).cloned() {
+                usage_tracking::tag(&mut judge, &group, "rank");
+            }
+            let (judged, status) = generate_with_retries(&judge, provider_config.clone(), tool_type, app_handle, None, cancel).await;
+            if let (Some(total), Some(u)) = (&mut response.usage, &judged.usage) {
+                total.prompt_tokens += u.prompt_tokens;
+                total.completion_tokens += u.completion_tokens;
+                total.total_tokens += u.total_tokens;
+            }
+            let scores = judged.parsed.as_ref().and_then(|p| candidates::parse_scores(p, response.candidates.len()));
+            match scores {
+                Some(scores) if status == usage_tracking::UsageStatus::Success => scores,
+                _ => {
+                    eprintln!("Ranking candidates failed ({}), falling back to the heuristic",
+                        judged.error.as_deref().unwrap_or("no scores"));
+                    candidates::heuristic_scores(&response.candidates)
+                }
+            }
+        }
+    };
+    candidates::order(response, &scores);
 }
 
 #[command]
 async fn list_available_models(
     provider_type: String,
     app_handle: tauri::AppHandle,
-) -> Result<Vec<ai_providers::ModelInfo>, String> {
+) -> Result<Vec<ai_providers::ModelInfo>, APIError> {
     let settings = settings::load_settings(&app_handle)
         .await
         .map_err(|e| format!("Failed to load settings: {}", e))?;
@@ -545,288 +1351,172 @@ async fn list_available_models(
         .ok_or_else(|| format!("Unknown provider: {}", provider_type))?
         .clone();
     
+    let overrides = model_capabilities::load_overrides(&app_handle).await?;
     let provider = create_provider(provider_config);
-    provider.list_models().await
+    let mut models = provider.list_models().await?;
+    for model in &mut models {
+        if let Some(o) = overrides.get(&model_capabilities::override_key(&provider_type, &model.id)) {
+            model.capabilities.apply(o);
+            model.context_length = Some(model.capabilities.context_window);
+        }
+    }
+    Ok(models)
 }
 
-// Keep the old API for backward compatibility
+/// Capabilities of `model` (the configured one if omitted), with user overrides applied.
 #[command]
-async fn generate_ai_response(
-    request: GenerateRequest,
+async fn get_model_capabilities(
+    provider_type: String,
+    model: Option<String>,
     app_handle: tauri::AppHandle,
-) -> Result<GenerateResponse, String> {
+) -> Result<model_capabilities::ModelCapabilities, APIError> {
     let settings = settings::load_settings(&app_handle)
         .await
         .map_err(|e| format!("Failed to load settings: {}", e))?;
-    
-    let provider = settings.get_active_provider();
-    
-    match provider.provider_type.as_str() {
-        "openai" => generate_openai_response(request, provider).await,
-        "ollama" => generate_ollama_response(request, provider).await,
-        "anthropic" => generate_anthropic_response(request, provider).await,
-        "gemini" => generate_gemini_response(request, provider).await,
-        _ => Err("Invalid provider specified".to_string()),
+
+    let mut provider_config = settings.get_provider(&provider_type)
+        .ok_or_else(|| format!("Unknown provider: {}", provider_type))?
+        .clone();
+    if let Some(model) = model {
+        provider_config.model = model;
     }
-}
 
-async fn generate_openai_response(
-    request: GenerateRequest,
-    provider: &AIProviderConfig,
-) -> Result<GenerateResponse, String> {
-    let api_key = match secure_storage::retrieve_api_key(&provider.provider_type) {
-        Ok(Some(key)) => key,
-        Ok(None) => return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "openai".to_string(),
-            success: false,
-            error: Some("OpenAI API key not configured".to_string()),
-        }),
-        Err(e) => return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "openai".to_string(),
-            success: false,
-            error: Some(format!("Failed to retrieve API key: {}", e)),
-        }),
-    };
-    
-    let client = reqwest::Client::new();
-    let openai_request = OpenAIRequest {
-        model: provider.model.clone(),
-        messages: vec![OpenAIMessage {
-            role: "user".to_string(),
-            content: request.prompt,
-        }],
-        temperature: request.temperature,
-        max_tokens: 2000,
-    };
-    
-    let url = format!("{}/chat/completions", provider.base_url);
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&openai_request)
-        .send()
-        .await
-        .map_err(|e| format!("OpenAI API request failed: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        eprintln!("OpenAI API error response: {}", error_text);
-        return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "openai".to_string(),
-            success: false,
-            error: Some("OpenAI request failed. Check your API key and model, then try again.".to_string()),
-        });
+    let overrides = model_capabilities::load_overrides(&app_handle).await?;
+    let key = model_capabilities::override_key(&provider_type, &provider_config.model);
+    let mut capabilities = create_provider(provider_config).capabilities().await;
+    if let Some(o) = overrides.get(&key) {
+        capabilities.apply(o);
     }
-    
-    let openai_response: OpenAIResponse = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse OpenAI response: {}", e))?;
-    
-    let content = openai_response
-        .choices
-        .first()
-        .map(|choice| choice.message.content.clone())
-        .unwrap_or_default();
-    
-    Ok(GenerateResponse {
-        content,
-        provider: "openai".to_string(),
-        success: true,
-        error: None,
-    })
+    Ok(capabilities)
 }
 
-async fn generate_ollama_response(
-    request: GenerateRequest,
-    provider: &AIProviderConfig,
-) -> Result<GenerateResponse, String> {
-    let client = reqwest::Client::new();
-    let ollama_request = OllamaRequest {
-        model: provider.model.clone(),
-        prompt: request.prompt,
-        stream: false,
-        options: OllamaOptions {
-            temperature: request.temperature,
-        },
-    };
-    
-    let url = format!("{}/api/generate", provider.base_url);
-    let response = client
-        .post(&url)
-        .json(&ollama_request)
-        .send()
-        .await
-        .map_err(|e| format!("Ollama API request failed: {}", e))?;
-    
-    if !response.status().is_success() {
-        return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "ollama".to_string(),
-            success: false,
-            error: Some(format!("Ollama not available or model not loaded")),
-        });
-    }
-    
-    let ollama_response: OllamaResponse = response
-        .json()
+/// Pre-flight token count of `request` against a provider's model (the preferred provider
+/// and its configured model by default), for a live counter next to long inputs.
+#[command]
+async fn estimate_tokens(
+    request: AIRequest,
+    provider_type: Option<String>,
+    model: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<token_estimation::TokenEstimate, APIError> {
+    let settings = settings::load_settings(&app_handle)
         .await
-        .map_err(|e| format!("Failed to parse Ollama response: {}", e))?;
-    
-    Ok(GenerateResponse {
-        content: ollama_response.response,
-        provider: "ollama".to_string(),
-        success: true,
-        error: None,
-    })
+        .map_err(|e| format!("Failed to load settings: {}", e))?;
+    let tool_type = tool_type_of(&request);
+    let mut request = request;
+    apply_tool_route(&mut request, &settings, &tool_type);
+
+    let provider_type = provider_type
+        .or_else(|| request.provider.clone())
+        .unwrap_or_else(|| settings.preferred_provider.clone());
+    let model = model.or_else(|| request.model.clone()).unwrap_or_else(|| {
+        settings.get_provider(&provider_type).map(|p| p.model.clone()).unwrap_or_default()
+    });
+    let capabilities = get_model_capabilities(provider_type.clone(), Some(model.clone()), app_handle).await?;
+    Ok(token_estimation::estimate(&request, &provider_type, &model, &capabilities))
 }
 
-async fn generate_anthropic_response(
-    request: GenerateRequest,
-    provider: &AIProviderConfig,
-) -> Result<GenerateResponse, String> {
-    let api_key = match secure_storage::retrieve_api_key(&provider.provider_type) {
-        Ok(Some(key)) => key,
-        Ok(None) => return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "anthropic".to_string(),
-            success: false,
-            error: Some("Anthropic API key not configured".to_string()),
-        }),
-        Err(e) => return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "anthropic".to_string(),
-            success: false,
-            error: Some(format!("Failed to retrieve API key: {}", e)),
-        }),
-    };
-
-    let client = reqwest::Client::new();
-    let anthropic_request = serde_json::json!({
-        "model": provider.model,
-        "max_tokens": 2000,
-        "messages": [{"role": "user", "content": request.prompt}]
-    });
+#[command]
+async fn load_model_capability_overrides(app_handle: tauri::AppHandle) -> Result<model_capabilities::CapabilityOverrides, String> {
+    model_capabilities::load_overrides(&app_handle).await
+}
 
-    let url = format!("{}/v1/messages", provider.base_url);
-    let response = client
-        .post(&url)
-        .header("x-api-key", &api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("Content-Type", "application/json")
-        .json(&anthropic_request)
-        .send()
-        .await
-        .map_err(|e| format!("Anthropic API request failed: {}", e))?;
-
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        eprintln!("Anthropic API error response: {}", error_text);
-        return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "anthropic".to_string(),
-            success: false,
-            error: Some("Anthropic request failed. Check your API key and model, then try again.".to_string()),
-        });
-    }
+#[command]
+async fn save_model_capability_overrides(
+    overrides: model_capabilities::CapabilityOverrides,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    model_capabilities::save_overrides(&app_handle, &overrides).await
+}
 
-    let anthropic_response: serde_json::Value = response
-        .json()
+/// Modelfile defaults and trained context length of an Ollama model (the configured one if `model` is omitted).
+#[command]
+async fn get_ollama_model_defaults(
+    model: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<ai_providers::OllamaModelDefaults, APIError> {
+    let settings = settings::load_settings(&app_handle)
         .await
-        .map_err(|e| format!("Failed to parse Anthropic response: {}", e))?;
-
-    let content = anthropic_response["content"][0]["text"]
-        .as_str()
-        .unwrap_or_default()
-        .to_string();
+        .map_err(|e| format!("Failed to load settings: {}", e))?;
 
-    Ok(GenerateResponse {
-        content,
-        provider: "anthropic".to_string(),
-        success: true,
-        error: None,
-    })
+    let model = model.unwrap_or_else(|| settings.ollama.model.clone());
+    ai_providers::OllamaProvider::new(settings.ollama).show_model(&model).await
 }
 
-async fn generate_gemini_response(
+// Keep the old API for backward compatibility. It now goes through the same path as
+// `generate_ai_response_v2` (fallbacks, retries, cache, usage) and flattens the result.
+#[command]
+async fn generate_ai_response(
     request: GenerateRequest,
-    provider: &AIProviderConfig,
+    app_handle: tauri::AppHandle,
 ) -> Result<GenerateResponse, String> {
-    let api_key = match secure_storage::retrieve_api_key(&provider.provider_type) {
-        Ok(Some(key)) => key,
-        Ok(None) => return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "gemini".to_string(),
-            success: false,
-            error: Some("Gemini API key not configured".to_string()),
-        }),
-        Err(e) => return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "gemini".to_string(),
-            success: false,
-            error: Some(format!("Failed to retrieve API key: {}", e)),
-        }),
+    let request = AIRequest {
+        prompt: request.prompt,
+        temperature: Some(request.temperature),
+        max_tokens: request.max_tokens,
+        system_message: request.system_message,
+        context: Some([("tool".to_string(), request.tool_type)].into()),
+        messages: vec![],
+        response_schema: None,
+        bypass_cache: false,
+        split_input: None,
+        provider: None,
+        model: None,
+        sampling: Default::default(),
+        candidates: None,
+        images: vec![],
+        tools: vec![],
+        tool_choice: Default::default(),
     };
 
-    let client = reqwest::Client::new();
-    let gemini_request = serde_json::json!({
-        "contents": [{
-            "parts": [{"text": request.prompt}]
-        }],
-        "generationConfig": {
-            "temperature": request.temperature,
-            "maxOutputTokens": 2000
-        }
-    });
+    let response = match run_generation(request.clone(), &app_handle, None, None).await {
+        Ok(response) => response,
+        Err(e) => AIResponse::from_error(&resolved_model(request, &app_handle).await, e),
+    };
+    Ok(GenerateResponse {
+        content: response.content,
+        provider: response.provider,
+        model: response.model,
+        success: response.success,
+        error: response.error,
+    })
+}
 
-    let url = format!("{}/models/{}:generateContent",
-        provider.base_url,
-        provider.model
-    );
-    
-    let response = client
-        .post(&url)
-        .header("x-goog-api-key", &api_key)
-        .header("Content-Type", "application/json")
-        .json(&gemini_request)
-        .send()
-        .await
-        .map_err(|e| format!("Gemini API request failed: {}", e))?;
-
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        eprintln!("Gemini API error response: {}", error_text);
-        return Ok(GenerateResponse {
-            content: String::new(),
-            provider: "gemini".to_string(),
-            success: false,
-            error: Some("Gemini request failed. Check your API key and model, then try again.".to_string()),
-        });
+/// The model `request` goes to first, for responses to requests that failed before
+/// reaching a provider; empty when settings can't be read.
+async fn resolved_model(mut request: AIRequest, app_handle: &tauri::AppHandle) -> String {
+    let Ok(settings) = settings::load_settings(app_handle).await else {
+        return String::new();
+    };
+    let tool_type = tool_type_of(&request);
+    apply_tool_route(&mut request, &settings, &tool_type);
+    match settings.provider_chain_for(request.provider.as_deref(), request.model.as_deref()) {
+        Ok(chain) => chain[0].model.clone(),
+        Err(_) => settings.get_active_provider().model.clone(),
     }
+}
 
-    let gemini_response: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse Gemini response: {}", e))?;
-
-    let content = gemini_response["candidates"][0]["content"]["parts"][0]["text"]
-        .as_str()
-        .unwrap_or_default()
-        .to_string();
-
-    Ok(GenerateResponse {
-        content,
-        provider: "gemini".to_string(),
-        success: true,
-        error: None,
+/// Text of a PDF, DOCX, PPTX or Markdown file, split into sections, e.g. to use a deck as
+/// `pitch_content`. Takes a path, or the file's name and base64 data from a file picker.
+#[command]
+async fn extract_document(
+    path: Option<String>,
+    file_name: Option<String>,
+    data: Option<String>,
+) -> Result<documents::Document, String> {
+    // PDF parsing in particular can take a while; keep it off the async runtime.
+    tokio::task::spawn_blocking(move || match (path, file_name, data) {
+        (_, Some(file_name), Some(data)) => {
+            let bytes = images::decode_data(&data).map_err(|e| format!("Failed to decode {}: {}", file_name, e))?;
+            documents::extract(&file_name, &bytes)
+        }
+        (Some(path), _, _) => documents::extract_file(&path),
+        _ => Err("Give a path, or a file name with data".to_string()),
     })
+    .await
+    .map_err(|e| format!("Failed to extract document: {}", e))?
 }
 
-
 #[command]
 async fn save_file_to_downloads(
     filename: String,
@@ -919,6 +1609,16 @@ async fn export_usage_data(app_handle: tauri::AppHandle) -> Result<String, Strin
     usage_tracking::export_usage_data(&app_handle).await
 }
 
+#[command]
+async fn get_response_cache_stats(app_handle: tauri::AppHandle) -> Result<response_cache::CacheStats, String> {
+    response_cache::stats(&app_handle)
+}
+
+#[command]
+async fn clear_response_cache(app_handle: tauri::AppHandle) -> Result<(), String> {
+    response_cache::clear(&app_handle)
+}
+
 #[command]
 async fn validate_api_key(provider: String, api_key: String) -> Result<key_validation::ValidationResult, String> {
     Ok(key_validation::validate_api_key(&provider, &api_key))
@@ -929,6 +1629,7 @@ async fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_fs::init())
+        .manage(CancellationRegistry::default())
         .setup(|app| {
             // Initialize usage tracking database
             let app_handle = app.handle().clone();
@@ -942,7 +1643,19 @@ async fn main() {
             load_settings,
             generate_ai_response,
             generate_ai_response_v2,
+            generate_ai_response_stream,
+            cancel_generation,
+            generate_business_ideas,
+            analyze_market,
+            score_pitch,
+            generate_prd,
+            compare_providers,
             list_available_models,
+            get_ollama_model_defaults,
+            get_model_capabilities,
+            estimate_tokens,
+            load_model_capability_overrides,
+            save_model_capability_overrides,
             test_provider_connection,
             test_custom_provider_connection,
             store_api_key,
@@ -952,6 +1665,7 @@ async fn main() {
             get_all_api_key_status,
             test_keychain_access,
             save_file_to_downloads,
+            extract_document,
             load_prompts,
             save_prompt,
             reset_prompt,
@@ -962,8 +1676,128 @@ async fn main() {
             get_usage_history,
             clear_usage_history,
             export_usage_data,
+            get_response_cache_stats,
+            clear_response_cache,
             validate_api_key
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error_handling::APIErrorType;
+    use settings::MockOptions;
+
+    /// A mock provider that fails the first `fail_times` attempts with `fail_with`, asking
+    /// for an immediate retry.
+    fn mock(fail_with: Option<APIErrorType>, fail_times: u32, latency_ms: u64) -> ProviderEnum {
+        create_provider(AIProviderConfig {
+            provider_type: "mock".to_string(),
+            model: "mock-model".to_string(),
+            enabled: true,
+            mock_options: MockOptions {
+                latency_ms,
+                chunk_delay_ms: 0,
+                fail_with,
+                fail_times,
+                retry_after: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn request() -> AIRequest {
+        AIRequest {
+            prompt: "pet care".to_string(),
+            temperature: Some(0.7),
+            max_tokens: None,
+            system_message: None,
+            context: Some([("tool".to_string(), "idea_forge".to_string())].into()),
+            messages: vec![],
+            response_schema: None,
+            bypass_cache: false,
+            split_input: None,
+            provider: None,
+            model: None,
+            sampling: Default::default(),
+            candidates: None,
+            images: vec![],
+            tools: vec![],
+            tool_choice: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backoff_recovers_from_transient_errors() {
+        let provider = mock(Some(APIErrorType::ServerError), 3, 0);
+        let response = generate_with_backoff(&provider, &request(), None, &mut None).await.unwrap().unwrap();
+        assert!(response.success);
+    }
+
+    #[tokio::test]
+    async fn test_backoff_gives_up_and_skips_permanent_errors() {
+        // One failure more than the retry budget.
+        let provider = mock(Some(APIErrorType::RateLimit), 4, 0);
+        let error = generate_with_backoff(&provider, &request(), None, &mut None).await.unwrap().unwrap_err();
+        assert_eq!(error.error_type, APIErrorType::RateLimit);
+
+        // Not retried: the first attempt's error comes straight back.
+        let provider = mock(Some(APIErrorType::InvalidApiKey), 1, 0);
+        let error = generate_with_backoff(&provider, &request(), None, &mut None).await.unwrap().unwrap_err();
+        assert_eq!(error.code, "invalid_api_key");
+    }
+
+    #[tokio::test]
+    async fn test_backoff_cancellation() {
+        let registry = CancellationRegistry::default();
+        let mut cancel = Some(registry.register("req"));
+        let provider = mock(None, 0, 10_000);
+        registry.cancel("req");
+
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            generate_with_backoff(&provider, &request(), None, &mut cancel),
+        ).await.unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_tool_route_fills_unset_fields() {
+        let mut settings = AppSettings::default();
+        settings.openai.enabled = true;
+        settings.tool_routing.insert("idea_forge".to_string(), settings::ToolRoute {
+            provider: Some("openai".to_string()),
+            model: Some("gpt-4o".to_string()),
+            temperature: Some(0.9),
+            max_tokens: Some(1200),
+        });
+
+        let mut routed = AIRequest { temperature: None, ..request() };
+        apply_tool_route(&mut routed, &settings, "idea_forge");
+        assert_eq!((routed.provider.as_deref(), routed.model.as_deref()), (Some("openai"), Some("gpt-4o")));
+        assert_eq!((routed.temperature, routed.max_tokens), (Some(0.9), Some(1200)));
+
+        // The request's own choices win, and the routed model stays with its provider.
+        let mut explicit = AIRequest { provider: Some("anthropic".to_string()), ..request() };
+        apply_tool_route(&mut explicit, &settings, "idea_forge");
+        assert_eq!((explicit.provider.as_deref(), explicit.model.as_deref()), (Some("anthropic"), None));
+        assert_eq!(explicit.temperature, Some(0.7));
+
+        // A disabled provider leaves the preferred one; the route's sampling still applies.
+        settings.openai.enabled = false;
+        let mut unavailable = AIRequest { temperature: None, ..request() };
+        apply_tool_route(&mut unavailable, &settings, "idea_forge");
+        assert_eq!((unavailable.provider, unavailable.model, unavailable.temperature), (None, None, Some(0.9)));
+
+        // Defaults for the built-in tools; nothing for others.
+        let mut compass = AIRequest { temperature: None, ..request() };
+        apply_tool_route(&mut compass, &AppSettings::default(), "global_compass");
+        assert_eq!((compass.provider, compass.temperature, compass.max_tokens), (None, Some(0.3), Some(3000)));
+        let mut other = AIRequest { temperature: None, ..request() };
+        apply_tool_route(&mut other, &AppSettings::default(), "unknown");
+        assert_eq!((other.temperature, other.max_tokens), (None, None));
+    }
+}
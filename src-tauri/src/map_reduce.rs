@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::ai_providers::AIRequest;
+use crate::model_capabilities::{CapabilitySource, ModelCapabilities};
+use crate::prompts;
+use crate::settings::MapReduceSettings;
+use crate::token_estimation::{self, EstimationMethod, TokenEstimate};
+
+/// Rounds of condensing before the final pass is sent regardless: notes on the input,
+/// notes on those notes, and so on.
+pub const MAX_ROUNDS: u32 = 3;
+
+/// Parts smaller than this would mostly be instructions, so a model whose window leaves
+/// less room is too small to split for.
+const MIN_CHUNK_TOKENS: u32 = 256;
+
+/// Boundaries tried in order when cutting text: paragraphs, lines, sentences, words.
+const SEPARATORS: &[&str] = &["\n\n", "\n", ". ", " "];
+
+/// A prompt as its `ToolPrompt` template and variables. When the rendered prompt is too
+/// long, `variable` is condensed part by part and the template rendered again with the
+/// result, so custom templates keep working.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitInput {
+    pub template: String,
+    pub variables: HashMap<String, String>,
+    /// The variable that may be split, e.g. "pitch_content".
+    pub variable: String,
+}
+
+/// Whether `request` should be condensed for this model rather than sent whole. Only
+/// known limits count: splitting on a guessed one could cut up input that would fit.
+pub fn should_split(request: &AIRequest, estimate: &TokenEstimate, settings: &MapReduceSettings, tool: &str) -> bool {
+    request.split_input.is_some()
+        && !estimate.fits
+        && estimate.capabilities_source != CapabilitySource::Default
+        && settings.tools.get(tool).is_some_and(|t| t.enabled)
+}
+
+/// What each part is condensed to, unless the tool's settings say otherwise.
+pub fn instruction_for(settings: &MapReduceSettings, tool: &str) -> String {
+    if let Some(instruction) = settings.tools.get(tool).and_then(|t| t.instruction.clone()) {
+        return instruction;
+    }
+    match tool {
+        "pitch_perfect" => "Condense this part of a pitch for a presentation coach who will review the whole pitch. \
+            Keep its structure, claims, numbers, audience hooks, stories and calls to action, and quote the opening, \
+            the closing and any memorable phrases word for word.",
+        "prd_generator" => "Condense these answers for a product manager who will write a PRD from them. \
+            Keep every requirement, constraint, user story, edge case, metric and acceptance criterion; drop only repetition.",
+        _ => "Condense this part of the input, keeping every fact, number and requirement; drop only repetition.",
+    }
+    .to_string()
+}
+
+/// Output budget of each map call.
+pub fn map_output_tokens(context_window: u32) -> u32 {
+    (context_window / 4).clamp(MIN_CHUNK_TOKENS, 1024)
+}
+
+/// Request condensing part `index` (1-based) of `count`.
+pub fn map_request(request: &AIRequest, split: &SplitInput, instruction: &str, part: &str, index: usize, count: usize, max_tokens: u32) -> AIRequest {
+    AIRequest {
+        prompt: format!(
+            "{}\n\nThis is part {} of {} of the {}. Reply with your notes only; they will be combined with the notes on the other parts.\n\n\"\"\"\n{}\n\"\"\"",
+            instruction,
+            index,
+            count,
+            split.variable.replace('_', " "),
+            part.trim()
+        ),
+        temperature: Some(request.effective_temperature().min(0.3)),
+        max_tokens: Some(max_tokens),
+        messages: vec![],
+        response_schema: None,
+        split_input: None,
+        candidates: None,
+        // The images and functions go with the reduce call, which sees the whole input.
+        images: vec![],
+        tools: vec![],
+        ..request.clone()
+    }
+}
+
+/// The final pass: the tool's own template, rendered with the condensed notes.
+pub fn reduce_request(request: &AIRequest, split: &SplitInput, notes: &str) -> AIRequest {
+    let mut variables = split.variables.clone();
+    variables.insert(
+        split.variable.clone(),
+        format!("(Condensed notes; the full {} was too long to include.)\n\n{}", split.variable.replace('_', " "), notes),
+    );
+    AIRequest {
+        prompt: prompts::render(&split.template, &variables),
+        split_input: None,
+        ..request.clone()
+    }
+}
+
+pub fn combine(notes: &[String]) -> String {
+    notes.iter()
+        .enumerate()
+        .map(|(i, note)| format!("Part {}:\n{}", i + 1, note.trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Tokens left for the text of each part once the instructions and the answer are
+/// accounted for, or `None` if the window is too small to split for.
+pub fn chunk_budget(request: &AIRequest, split: &SplitInput, instruction: &str, provider_type: &str, model: &str, capabilities: &ModelCapabilities) -> Option<u32> {
+    let max_tokens = map_output_tokens(capabilities.context_window);
+    let empty = map_request(request, split, instruction, "", 1, 1, max_tokens);
+    let overhead = token_estimation::estimate(&empty, provider_type, model, capabilities).prompt_tokens;
+    let budget = capabilities.context_window.saturating_sub(overhead + max_tokens);
+    (budget >= MIN_CHUNK_TOKENS).then_some(budget)
+}
+
+/// Cut `text` into parts of at most `max_tokens`, at the coarsest boundary that works.
+pub fn split(text: &str, max_tokens: u32, method: EstimationMethod) -> Vec<String> {
+    split_at(text, max_tokens, method, SEPARATORS)
+        .into_iter()
+        .filter(|part| !part.trim().is_empty())
+        .collect()
+}
+
+fn split_at(text: &str, max_tokens: u32, method: EstimationMethod, separators: &[&str]) -> Vec<String> {
+    let fits = |text: &str| token_estimation::count_tokens(text, method) <= max_tokens;
+    if fits(text) {
+        return vec![text.to_string()];
+    }
+    let Some((separator, finer)) = separators.split_first() else {
+        return cut(text, max_tokens, method);
+    };
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for piece in text.split_inclusive(separator) {
+        if fits(&format!("{}{}", current, piece)) {
+            current.push_str(piece);
+            continue;
+        }
+        if !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+        }
+        if fits(piece) {
+            current = piece.to_string();
+        } else {
+            parts.extend(split_at(piece, max_tokens, method, finer));
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Last resort for text without spaces: the longest runs of characters that fit.
+fn cut(text: &str, max_tokens: u32, method: EstimationMethod) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let fits = |range: &[char]| token_estimation::count_tokens(&range.iter().collect::<String>(), method) <= max_tokens;
+    let mut parts = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let (mut low, mut high) = (start + 1, chars.len());
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if fits(&chars[start..mid]) { low = mid } else { high = mid - 1 }
+        }
+        parts.push(chars[start..low].iter().collect());
+        start = low;
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pitch_request(content: &str) -> AIRequest {
+        let split = SplitInput {
+            template: "Coach this {audience} pitch:\n\n{pitch_content}".to_string(),
+            variables: [
+                ("audience".to_string(), "investor".to_string()),
+                ("pitch_content".to_string(), content.to_string()),
+            ].into(),
+            variable: "pitch_content".to_string(),
+        };
+        AIRequest {
+            prompt: prompts::render(&split.template, &split.variables),
+            temperature: Some(0.7),
+            max_tokens: Some(1500),
+            system_message: Some("You are a pitch coach.".to_string()),
+            context: Some([("tool".to_string(), "pitch_perfect".to_string())].into()),
+            messages: vec![],
+            response_schema: None,
+            bypass_cache: false,
+            split_input: Some(split),
+            provider: None,
+            model: None,
+            sampling: Default::default(),
+            candidates: None,
+            images: vec![],
+            tools: vec![],
+            tool_choice: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_split_prefers_coarse_boundaries() {
+        let paragraphs = ["First paragraph about the problem. ".repeat(20), "Second about the market. ".repeat(20), "Third about the ask. ".repeat(20)];
+        let text = paragraphs.join("\n\n");
+        let parts = split(&text, 200, EstimationMethod::Heuristic);
+        assert_eq!(parts.len(), 3);
+        assert!(parts[1].starts_with("Second"));
+        assert_eq!(parts.concat(), text);
+
+        // One huge paragraph falls back to sentences, then to raw characters.
+        let sentences = split(&"We sell bread. ".repeat(200), 100, EstimationMethod::Cl100kBase);
+        assert!(sentences.len() > 1 && sentences.iter().all(|p| p.ends_with(". ")));
+        let unbroken = split(&"x".repeat(2000), 100, EstimationMethod::Heuristic);
+        assert_eq!(unbroken.len(), 5);
+    }
+
+    #[test]
+    fn test_should_split_and_requests() {
+        let request = pitch_request(&"Our bakery cuts food waste by half. ".repeat(800));
+        let settings = MapReduceSettings::default();
+        let small = ModelCapabilities { context_window: 4096, source: CapabilitySource::Provider, ..Default::default() };
+        let estimate = token_estimation::estimate(&request, "ollama", "llama3.2", &small);
+        assert!(should_split(&request, &estimate, &settings, "pitch_perfect"));
+        assert!(!should_split(&request, &estimate, &settings, "idea_forge"));
+        let guessed = ModelCapabilities { source: CapabilitySource::Default, ..small.clone() };
+        assert!(!should_split(&request, &token_estimation::estimate(&request, "ollama", "llama3.2", &guessed), &settings, "pitch_perfect"));
+
+        let split_input = request.split_input.clone().unwrap();
+        let instruction = instruction_for(&settings, "pitch_perfect");
+        let budget = chunk_budget(&request, &split_input, &instruction, "ollama", "llama3.2", &small).unwrap();
+        let parts = split(&split_input.variables["pitch_content"], budget, EstimationMethod::Heuristic);
+        for (i, part) in parts.iter().enumerate() {
+            let map = map_request(&request, &split_input, &instruction, part, i + 1, parts.len(), map_output_tokens(4096));
+            assert!(token_estimation::estimate(&map, "ollama", "llama3.2", &small).fits);
+            assert_eq!(map.system_message, request.system_message);
+        }
+
+        let reduce = reduce_request(&request, &split_input, &combine(&["Waste halved.".to_string(), "Asks for $500k.".to_string()]));
+        assert!(reduce.prompt.starts_with("Coach this investor pitch:\n\n(Condensed notes"));
+        assert!(reduce.prompt.ends_with("Part 1:\nWaste halved.\n\nPart 2:\nAsks for $500k."));
+        assert_eq!(reduce.max_tokens, Some(1500));
+        assert!(reduce.split_input.is_none());
+    }
+}
@@ -0,0 +1,445 @@
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use crate::ai_providers::{AIProvider, AIRequest, AIResponse, ChatRole, DeltaSink, ModelInfo, TokenUsage};
+use crate::function_calling::{ToolCall, ToolChoice};
+use crate::error_handling::{APIError, APIErrorType};
+use crate::model_capabilities::{CapabilitySource, ModelCapabilities};
+use crate::settings::AIProviderConfig;
+
+/// Offline provider for demos, workshops and tests. Answers are deterministic: a
+/// per-tool template (or the user's own from `MockOptions::responses`), or a value
+/// generated from the request's `response_schema`. Offered functions are each called once,
+/// with arguments generated from their schema, before answering. Latency and failures
+/// are configured through `MockOptions`.
+pub struct MockProvider {
+    config: AIProviderConfig,
+    /// Attempts made through this instance; `generate_with_retries` creates one provider
+    /// per request, so this counts the retries of a single request.
+    attempts: AtomicU32,
+}
+
+impl MockProvider {
+    pub fn new(config: AIProviderConfig) -> Self {
+        Self { config, attempts: AtomicU32::new(0) }
+    }
+
+    /// The configured failure for this attempt, if any.
+    fn injected_failure(&self) -> Option<APIError> {
+        let options = &self.config.mock_options;
+        let error_type = options.fail_with.clone()?;
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+        if options.fail_times > 0 && attempt >= options.fail_times {
+            return None;
+        }
+
+        let mut error = match error_type {
+            APIErrorType::NetworkError => APIError::network_error("mock", "injected network error"),
+            APIErrorType::Cancelled => APIError::cancelled("mock"),
+            other => APIError::from_status_code(status_for(&other), "mock", None),
+        };
+        error.retry_after = options.retry_after;
+        Some(error)
+    }
+
+    fn content(&self, request: &AIRequest) -> String {
+        if let Some(schema) = &request.response_schema {
+            return sample_for(schema, "value").to_string();
+        }
+
+        let tool = request.context.as_ref()
+            .and_then(|ctx| ctx.get("tool"))
+            .map(|tool| tool.as_str())
+            .unwrap_or("unknown");
+        let template = match self.config.mock_options.responses.get(tool) {
+            Some(template) => template.as_str(),
+            None => default_template(tool),
+        };
+        // After function calls the prompt is in the history.
+        let conversation = request.conversation();
+        let prompt = conversation.iter().rev().find(|m| m.role == ChatRole::User).map_or("", |m| m.content.trim());
+        let mut content = template
+            .replace("{prompt}", prompt)
+            .replace("{model}", &self.config.model);
+        let results: Vec<String> = conversation.iter()
+            .filter(|m| m.role == ChatRole::Tool)
+            .map(|m| format!("- {}: {}", m.tool_calls.first().map_or("function", |c| c.name.as_str()), m.content))
+            .collect();
+        if !results.is_empty() {
+            content.push_str(&format!("\n\nFunction results:\n{}", results.join("\n")));
+        }
+        content
+    }
+
+    /// Calls of every offered function, unless their results are in or an answer is due.
+    fn tool_calls(&self, request: &AIRequest) -> Vec<ToolCall> {
+        let answered = request.conversation().last().is_some_and(|m| m.role == ChatRole::Tool);
+        if answered || request.tool_choice == ToolChoice::None {
+            return Vec::new();
+        }
+        request.tools.iter()
+            .enumerate()
+            .map(|(i, tool)| ToolCall {
+                id: format!("call_{}", i + 1),
+                name: tool.name.clone(),
+                arguments: sample_for(&tool.parameters, &tool.name),
+            })
+            .collect()
+    }
+
+    fn response(&self, request: &AIRequest, content: String) -> AIResponse {
+        let prompt_chars: usize = request.conversation().iter().map(|m| m.content.len()).sum::<usize>()
+            + request.system_message.as_deref().map_or(0, str::len);
+        let prompt_tokens = (prompt_chars / 4) as u32;
+        let completion_tokens = (content.len() / 4) as u32;
+
+        AIResponse {
+            content,
+            provider: "mock".to_string(),
+            model: self.config.model.clone(),
+            success: true,
+            error: None,
+            usage: Some(TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            }),
+            error_type: None,
+            fallbacks: Vec::new(),
+            parsed: None,
+            cached: false,
+            error_details: None,
+            sampling: None,
+            candidates: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_runs: Vec::new(),
+        }
+    }
+}
+
+fn status_for(error_type: &APIErrorType) -> u16 {
+    match error_type {
+        APIErrorType::RateLimit => 429,
+        APIErrorType::QuotaExceeded => 402,
+        APIErrorType::InvalidApiKey => 401,
+        APIErrorType::ModelNotFound => 404,
+        APIErrorType::InvalidRequest => 400,
+        APIErrorType::ServerError => 500,
+        APIErrorType::NetworkError | APIErrorType::Cancelled | APIErrorType::Unknown => 418,
+    }
+}
+
+fn default_template(tool: &str) -> &'static str {
+    match tool {
+        "idea_forge" => "## Business Ideas (mock)\n\n\
+            1. **LocalLoop** - A neighbourhood marketplace for {prompt}. Target market: busy urban households. \
+            Value proposition: same-day pickup from people nearby. Challenge: building early supply.\n\n\
+            2. **Subscribely** - A subscription box built around {prompt}. Target market: gift buyers. \
+            Value proposition: curated, recurring surprises. Challenge: churn after the first months.\n\n\
+            3. **CoachKit** - Online courses and coaching on {prompt}. Target market: beginners. \
+            Value proposition: guided progress at their own pace. Challenge: standing out from free content.",
+        "global_compass" => "## Market Analysis (mock)\n\n\
+            **Market opportunity:** Steady demand with room for a focused entrant.\n\n\
+            **Cultural considerations:** Relationships and local references matter more than price.\n\n\
+            **Regulatory requirements:** Standard business registration, consumer protection and data privacy rules.\n\n\
+            **Entry strategy:** Start with a local partner and one city, then expand.\n\n\
+            **Risks:** Currency swings and established local competitors.",
+        "pitch_perfect" => "## Pitch Feedback (mock)\n\n\
+            **Scores:** Clarity 7/10, Persuasiveness 6/10, Audience fit 8/10, Call to action 5/10\n\n\
+            **Strengths:** A clear problem statement and a concrete example.\n\n\
+            **Improvements:** Lead with the outcome and end with one specific ask.",
+        "prd_generator" => "# Product Requirements Document (mock)\n\n\
+            ## 1. Executive Summary\n\nA first version of the feature described below.\n\n\
+            ## 2. Problem Statement\n\n{prompt}\n\n\
+            ## 3. Functional Requirements\n\n- Users can complete the core flow end to end.\n\n\
+            ## 4. Acceptance Criteria\n\n- The core flow works on every supported platform.",
+        _ => "Mock response from {model}.\n\nYou asked: {prompt}",
+    }
+}
+
+/// A deterministic value satisfying `schema`, for the subset `structured_output::validate`
+/// checks. Strings are filled in from the schema's description or the property name.
+fn sample_for(schema: &Value, name: &str) -> Value {
+    if let Some(value) = schema.get("const") {
+        return value.clone();
+    }
+    if let Some(first) = schema.get("enum").and_then(|e| e.as_array()).and_then(|e| e.first()) {
+        return first.clone();
+    }
+    for key in ["anyOf", "oneOf"] {
+        if let Some(first) = schema.get(key).and_then(|b| b.as_array()).and_then(|b| b.first()) {
+            return sample_for(first, name);
+        }
+    }
+
+    let schema_type = match schema.get("type") {
+        Some(Value::String(t)) => t.as_str(),
+        Some(Value::Array(ts)) => ts.iter().filter_map(|t| t.as_str()).find(|t| *t != "null").unwrap_or("null"),
+        _ if schema.get("properties").is_some() => "object",
+        _ if schema.get("items").is_some() => "array",
+        _ => "string",
+    };
+
+    match schema_type {
+        "object" => {
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            let object: serde_json::Map<String, Value> = properties.into_iter()
+                .flatten()
+                .map(|(key, child)| (key.clone(), sample_for(child, key)))
+                .collect();
+            Value::Object(object)
+        }
+        "array" => {
+            let min = schema.get("minItems").and_then(|m| m.as_u64()).unwrap_or(0);
+            let max = schema.get("maxItems").and_then(|m| m.as_u64()).unwrap_or(3);
+            let item_schema = schema.get("items").cloned().unwrap_or(json!({}));
+            let item_name = name.trim_end_matches('s');
+            Value::Array((0..min.max(max.min(3))).map(|_| sample_for(&item_schema, item_name)).collect())
+        }
+        "integer" | "number" => {
+            let min = schema.get("minimum").and_then(|m| m.as_f64());
+            let max = schema.get("maximum").and_then(|m| m.as_f64());
+            let value = match (min, max) {
+                (Some(min), Some(max)) => ((min + max) / 2.0).ceil(),
+                (Some(min), None) => min,
+                (None, Some(max)) => max.min(1.0),
+                (None, None) => 1.0,
+            };
+            json!(value as i64)
+        }
+        "boolean" => json!(true),
+        "null" => Value::Null,
+        _ => {
+            let description = schema.get("description").and_then(|d| d.as_str()).unwrap_or(name);
+            let mut text = format!("Sample {}", description.to_lowercase());
+            if let Some(min) = schema.get("minLength").and_then(|m| m.as_u64()) {
+                while (text.chars().count() as u64) < min {
+                    text.push('.');
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(|m| m.as_u64()) {
+                text = text.chars().take(max as usize).collect();
+            }
+            json!(text)
+        }
+    }
+}
+
+impl AIProvider for MockProvider {
+    async fn generate(&self, request: &AIRequest) -> Result<AIResponse, APIError> {
+        tokio::time::sleep(Duration::from_millis(self.config.mock_options.latency_ms)).await;
+        if let Some(error) = self.injected_failure() {
+            return Err(error);
+        }
+        let tool_calls = self.tool_calls(request);
+        if !tool_calls.is_empty() {
+            return Ok(AIResponse { tool_calls, ..self.response(request, String::new()) });
+        }
+        Ok(self.response(request, self.content(request)))
+    }
+
+    async fn generate_stream(&self, request: &AIRequest, on_delta: DeltaSink<'_>) -> Result<AIResponse, APIError> {
+        tokio::time::sleep(Duration::from_millis(self.config.mock_options.latency_ms)).await;
+        if let Some(error) = self.injected_failure() {
+            return Err(error);
+        }
+
+        // One delta per word (keeping its trailing whitespace), like a real token stream.
+        let content = self.content(request);
+        let mut start = 0;
+        for (i, c) in content.char_indices() {
+            if c.is_whitespace() && !content[i + c.len_utf8()..].starts_with(char::is_whitespace) {
+                on_delta(&content[start..i + c.len_utf8()]);
+                start = i + c.len_utf8();
+                tokio::time::sleep(Duration::from_millis(self.config.mock_options.chunk_delay_ms)).await;
+            }
+        }
+        if start < content.len() {
+            on_delta(&content[start..]);
+        }
+        Ok(self.response(request, content))
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, APIError> {
+        Ok(vec![ModelInfo {
+            id: self.config.model.clone(),
+            name: self.config.model.clone(),
+            description: Some("Offline mock model with canned responses".to_string()),
+            context_length: Some(self.capabilities().await.context_window),
+            provider: "mock".to_string(),
+            capabilities: self.capabilities().await,
+        }])
+    }
+
+    async fn capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            context_window: 128_000,
+            max_output_tokens: 8_192,
+            vision: false,
+            json_mode: true,
+            tool_calling: true,
+            source: CapabilitySource::Provider,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candidates::CandidateOptions;
+    use crate::function_calling;
+    use crate::settings::MockOptions;
+    use crate::structured_output;
+    use std::sync::Mutex;
+
+    fn provider(options: MockOptions) -> MockProvider {
+        MockProvider::new(AIProviderConfig {
+            provider_type: "mock".to_string(),
+            model: "mock-model".to_string(),
+            enabled: true,
+            mock_options: MockOptions { latency_ms: 0, chunk_delay_ms: 0, ..options },
+            ..Default::default()
+        })
+    }
+
+    fn request(tool: &str, prompt: &str) -> AIRequest {
+        AIRequest {
+            prompt: prompt.to_string(),
+            temperature: Some(0.7),
+            max_tokens: None,
+            system_message: None,
+            context: Some([("tool".to_string(), tool.to_string())].into()),
+            messages: vec![],
+            response_schema: None,
+            bypass_cache: false,
+            split_input: None,
+            provider: None,
+            model: None,
+            sampling: Default::default(),
+            candidates: None,
+            images: vec![],
+            tools: vec![],
+            tool_choice: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_templates_and_streaming() {
+        let mock = provider(MockOptions {
+            responses: [("pitch_perfect".to_string(), "Nice pitch about {prompt}".to_string())].into(),
+            ..Default::default()
+        });
+
+        let ideas = mock.generate(&request("idea_forge", "pet care")).await.unwrap();
+        assert!(ideas.success);
+        assert!(ideas.content.contains("marketplace for pet care"));
+        assert_eq!(ideas.content, mock.generate(&request("idea_forge", "pet care")).await.unwrap().content);
+
+        let deltas = Mutex::new(Vec::new());
+        let on_delta = |delta: &str| deltas.lock().unwrap().push(delta.to_string());
+        let streamed = mock.generate_stream(&request("pitch_perfect", "solar  roofs"), &on_delta).await.unwrap();
+        assert_eq!(streamed.content, "Nice pitch about solar  roofs");
+        assert_eq!(deltas.lock().unwrap().concat(), streamed.content);
+        assert_eq!(deltas.lock().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_schema_sample_validates() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "ideas": {"type": "array", "minItems": 1, "items": {"type": "object", "properties": {
+                    "name": {"type": "string", "minLength": 40},
+                    "score": {"type": "integer", "minimum": 1, "maximum": 10},
+                    "market": {"enum": ["b2b", "b2c"]}
+                }, "required": ["name", "score", "market"], "additionalProperties": false}}
+            },
+            "required": ["ideas"]
+        });
+        let mut structured = request("idea_forge", "pet care");
+        structured.response_schema = Some(schema.clone());
+
+        let response = provider(MockOptions::default()).generate(&structured).await.unwrap();
+        let value = structured_output::parse_json(&response.content).unwrap();
+        assert!(structured_output::validate(&value, &schema).is_empty());
+        assert_eq!(value["ideas"][0]["score"], json!(6));
+    }
+
+    #[tokio::test]
+    async fn test_candidates_fan_out() {
+        let config = AIProviderConfig {
+            provider_type: "mock".to_string(),
+            model: "mock-model".to_string(),
+            mock_options: MockOptions { latency_ms: 0, chunk_delay_ms: 0, ..Default::default() },
+            ..Default::default()
+        };
+        let single = crate::ai_providers::create_provider(config.clone()).generate(&request("idea_forge", "pet care")).await.unwrap();
+        assert!(single.candidates.is_empty());
+
+        let mut several = request("idea_forge", "pet care");
+        several.candidates = Some(CandidateOptions { count: 3, ranking: Default::default() });
+        let deltas = Mutex::new(Vec::new());
+        let on_delta = |delta: &str| deltas.lock().unwrap().push(delta.to_string());
+        let response = crate::ai_providers::create_provider(config).generate_stream(&several, &on_delta).await.unwrap();
+        assert_eq!(response.candidates.len(), 3);
+        assert_eq!(response.usage.unwrap().total_tokens, 3 * single.usage.unwrap().total_tokens);
+        // Left to the caller, after ranking.
+        assert!(deltas.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_function_call_turns() {
+        let config = AIProviderConfig {
+            provider_type: "mock".to_string(),
+            model: "mock-model".to_string(),
+            mock_options: MockOptions { latency_ms: 0, chunk_delay_ms: 0, ..Default::default() },
+            ..Default::default()
+        };
+        let provider = crate::ai_providers::create_provider(config);
+        let mut first = request("global_compass", "Coffee subscriptions in Germany");
+        first.tools = function_calling::resolve(&function_calling::registry()).unwrap();
+        first.response_schema = Some(json!({"type": "object", "required": ["summary"], "properties": {"summary": {"type": "string"}}}));
+
+        // A turn that calls functions isn't validated against the schema.
+        let calling = provider.generate(&first).await.unwrap();
+        assert!(calling.success);
+        let names: Vec<&str> = calling.tool_calls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["market_size", "country_profile"]);
+
+        let runs: Vec<_> = calling.tool_calls.iter().map(function_calling::run).collect();
+        let second = function_calling::follow_up(&first, &calling, &runs);
+        let roles: Vec<ChatRole> = second.conversation().iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![ChatRole::User, ChatRole::Assistant, ChatRole::Tool, ChatRole::Tool]);
+
+        let answer = provider.generate(&AIRequest { response_schema: None, ..second.clone() }).await.unwrap();
+        assert!(answer.tool_calls.is_empty());
+        assert!(answer.content.contains("Function results:\n- market_size: {"));
+
+        // Forced to answer, even with no results yet.
+        let forced = provider.generate(&AIRequest { tool_choice: function_calling::ToolChoice::None, ..first }).await.unwrap();
+        assert!(forced.tool_calls.is_empty() && forced.parsed.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures() {
+        // The first `fail_times` attempts fail.
+        let flaky = provider(MockOptions { fail_with: Some(APIErrorType::RateLimit), fail_times: 2, retry_after: Some(0), ..Default::default() });
+        for _ in 0..2 {
+            let error = flaky.generate(&request("idea_forge", "x")).await.unwrap_err();
+            assert_eq!(error.error_type, APIErrorType::RateLimit);
+            assert_eq!(error.code, "rate_limit");
+            assert_eq!(error.status_code, Some(429));
+            assert_eq!(error.retry_after, Some(0));
+        }
+        assert!(flaky.generate(&request("idea_forge", "x")).await.unwrap().success);
+
+        // Every attempt fails when `fail_times` is 0.
+        let bad_key = provider(MockOptions { fail_with: Some(APIErrorType::InvalidApiKey), ..Default::default() });
+        for _ in 0..3 {
+            let error = bad_key.generate(&request("idea_forge", "x")).await.unwrap_err();
+            assert_eq!(error.error_type, APIErrorType::InvalidApiKey);
+            assert!(!error.should_retry());
+        }
+    }
+}
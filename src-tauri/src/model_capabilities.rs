@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::{AppHandle, Manager};
+
+/// Limits and features a model is known to have. Providers fill this from their own
+/// metadata where they expose any, otherwise from the bundled table below; user overrides
+/// (`model_capabilities.json`) are applied last.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelCapabilities {
+    /// Input + output tokens the model can attend to.
+    pub context_window: u32,
+    pub max_output_tokens: u32,
+    pub vision: bool,
+    /// Can be constrained to emit valid JSON.
+    pub json_mode: bool,
+    pub tool_calling: bool,
+    pub source: CapabilitySource,
+}
+
+/// Where a [`ModelCapabilities`] value came from, so the UI can flag guesses.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CapabilitySource {
+    /// Reported by the provider's API.
+    Provider,
+    /// Bundled table, matched by model name prefix.
+    Bundled,
+    /// Edited by the user.
+    Override,
+    /// Unknown model; conservative defaults.
+    Default,
+}
+
+impl Default for ModelCapabilities {
+    fn default() -> Self {
+        Self {
+            context_window: 4096,
+            max_output_tokens: 2048,
+            vision: false,
+            json_mode: false,
+            tool_calling: false,
+            source: CapabilitySource::Default,
+        }
+    }
+}
+
+/// User correction for one model; unset fields keep the resolved value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CapabilityOverride {
+    #[serde(default)]
+    pub context_window: Option<u32>,
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+    #[serde(default)]
+    pub vision: Option<bool>,
+    #[serde(default)]
+    pub json_mode: Option<bool>,
+    #[serde(default)]
+    pub tool_calling: Option<bool>,
+}
+
+/// Overrides keyed by `"{provider_type}/{model}"`, e.g. `"ollama/llama3.1:8b"`.
+pub type CapabilityOverrides = HashMap<String, CapabilityOverride>;
+
+impl ModelCapabilities {
+    pub fn apply(&mut self, o: &CapabilityOverride) {
+        if *o == CapabilityOverride::default() {
+            return;
+        }
+        self.context_window = o.context_window.unwrap_or(self.context_window);
+        self.max_output_tokens = o.max_output_tokens.unwrap_or(self.max_output_tokens);
+        self.vision = o.vision.unwrap_or(self.vision);
+        self.json_mode = o.json_mode.unwrap_or(self.json_mode);
+        self.tool_calling = o.tool_calling.unwrap_or(self.tool_calling);
+        self.source = CapabilitySource::Override;
+    }
+}
+
+// (table, model prefix, context window, max output, vision, json mode, tool calling)
+// The longest matching prefix wins, so "gpt-4-turbo" is not mistaken for "gpt-4".
+const BUNDLED: &[(&str, &str, u32, u32, bool, bool, bool)] = &[
+    ("openai", "gpt-5", 400_000, 128_000, true, true, true),
+    ("openai", "gpt-4.1", 1_047_576, 32_768, true, true, true),
+    ("openai", "gpt-4o", 128_000, 16_384, true, true, true),
+    ("openai", "gpt-4-turbo", 128_000, 4_096, true, true, true),
+    ("openai", "gpt-4", 8_192, 8_192, false, false, true),
+    ("openai", "gpt-3.5-turbo", 16_385, 4_096, false, true, true),
+    ("openai", "o1", 200_000, 100_000, true, true, true),
+    ("openai", "o3", 200_000, 100_000, true, true, true),
+    ("openai", "o4-mini", 200_000, 100_000, true, true, true),
+    ("anthropic", "claude-3-haiku", 200_000, 4_096, true, false, true),
+    ("anthropic", "claude-3-sonnet", 200_000, 4_096, true, false, true),
+    ("anthropic", "claude-3-opus", 200_000, 4_096, true, false, true),
+    ("anthropic", "claude-3-5-haiku", 200_000, 8_192, true, false, true),
+    ("anthropic", "claude-3-5-sonnet", 200_000, 8_192, true, false, true),
+    ("anthropic", "claude-3-7-sonnet", 200_000, 64_000, true, false, true),
+    ("anthropic", "claude-sonnet-4", 200_000, 64_000, true, false, true),
+    ("anthropic", "claude-opus-4", 200_000, 32_000, true, false, true),
+    ("anthropic", "claude-haiku-4", 200_000, 64_000, true, false, true),
+    ("gemini", "gemini-pro", 32_760, 8_192, false, false, true),
+    ("gemini", "gemini-1.5-pro", 2_097_152, 8_192, true, true, true),
+    ("gemini", "gemini-1.5-flash", 1_048_576, 8_192, true, true, true),
+    ("gemini", "gemini-2.0-flash", 1_048_576, 8_192, true, true, true),
+    ("gemini", "gemini-2.5-pro", 1_048_576, 65_536, true, true, true),
+    ("gemini", "gemini-2.5-flash", 1_048_576, 65_536, true, true, true),
+];
+
+/// Which bundled tables apply to a provider type. Azure deployments run OpenAI models;
+/// OpenAI-compatible gateways may serve anything.
+fn tables_for(provider_type: &str) -> &'static [&'static str] {
+    match provider_type {
+        "openai" | "azure" => &["openai"],
+        "anthropic" => &["anthropic"],
+        "gemini" => &["gemini"],
+        "openai_compatible" => &["openai", "anthropic", "gemini"],
+        _ => &[],
+    }
+}
+
+/// Look `model` up in the bundled table. Accepts Gemini's "models/" prefix and
+/// gateway-style "vendor/model" names.
+pub fn bundled(provider_type: &str, model: &str) -> Option<ModelCapabilities> {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    let tables = tables_for(provider_type);
+    BUNDLED
+        .iter()
+        .filter(|entry| tables.contains(&entry.0) && model.starts_with(entry.1))
+        .max_by_key(|entry| entry.1.len())
+        .map(|&(_, _, context_window, max_output_tokens, vision, json_mode, tool_calling)| ModelCapabilities {
+            context_window,
+            max_output_tokens,
+            vision,
+            json_mode,
+            tool_calling,
+            source: CapabilitySource::Bundled,
+        })
+}
+
+/// Bundled capabilities, or the conservative defaults for an unknown model.
+pub fn lookup(provider_type: &str, model: &str) -> ModelCapabilities {
+    bundled(provider_type, model).unwrap_or_default()
+}
+
+pub fn override_key(provider_type: &str, model: &str) -> String {
+    format!("{}/{}", provider_type, model)
+}
+
+pub fn get_overrides_path(app_handle: &AppHandle) -> Result<std::path::PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+
+    std::fs::create_dir_all(&app_data_dir)
+        .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+
+    Ok(app_data_dir.join("model_capabilities.json"))
+}
+
+pub async fn load_overrides(app_handle: &AppHandle) -> Result<CapabilityOverrides, String> {
+    let path = get_overrides_path(app_handle)?;
+
+    if !path.exists() {
+        return Ok(CapabilityOverrides::new());
+    }
+
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| format!("Failed to read model capabilities file: {}", e))?;
+
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse model capabilities file: {}", e))
+}
+
+pub async fn save_overrides(app_handle: &AppHandle, overrides: &CapabilityOverrides) -> Result<(), String> {
+    let path = get_overrides_path(app_handle)?;
+
+    let contents = serde_json::to_string_pretty(overrides)
+        .map_err(|e| format!("Failed to serialize model capabilities: {}", e))?;
+
+    tokio::fs::write(&path, contents)
+        .await
+        .map_err(|e| format!("Failed to write model capabilities file: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let mini = lookup("openai", "gpt-4o-mini-2024-07-18");
+        assert_eq!(mini.max_output_tokens, 16_384);
+        assert_eq!(lookup("openai", "gpt-4-0613").context_window, 8_192);
+        assert_eq!(lookup("openai", "gpt-4-turbo").context_window, 128_000);
+        assert_eq!(lookup("azure", "gpt-4o").source, CapabilitySource::Bundled);
+    }
+
+    #[test]
+    fn test_model_name_prefixes_and_unknown_models() {
+        assert_eq!(lookup("gemini", "models/gemini-1.5-pro-002").context_window, 2_097_152);
+        assert!(lookup("openai_compatible", "anthropic/claude-3-5-sonnet").vision);
+
+        // Tables don't leak across providers.
+        assert!(bundled("gemini", "gpt-4o").is_none());
+        assert_eq!(lookup("ollama", "llama3.1:8b"), ModelCapabilities::default());
+    }
+
+    #[test]
+    fn test_apply_override() {
+        let mut caps = lookup("anthropic", "claude-sonnet-4-5");
+        caps.apply(&CapabilityOverride::default());
+        assert_eq!(caps.source, CapabilitySource::Bundled);
+
+        caps.apply(&CapabilityOverride { json_mode: Some(true), ..Default::default() });
+        assert!(caps.json_mode);
+        assert_eq!(caps.context_window, 200_000);
+        assert_eq!(caps.source, CapabilitySource::Override);
+    }
+}
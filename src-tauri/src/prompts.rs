@@ -38,6 +38,13 @@ impl Default for PromptCollection {
     }
 }
 
+/// Fill `{name}` placeholders, as `replacePromptVariables` does in the frontend.
+pub fn render(template: &str, variables: &HashMap<String, String>) -> String {
+    variables.iter().fold(template.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
 pub fn get_default_prompts() -> HashMap<String, ToolPrompt> {
     let mut prompts = HashMap::new();
 
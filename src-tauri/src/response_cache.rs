@@ -0,0 +1,299 @@
+use chrono::{Duration, Utc};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use std::path::PathBuf;
+
+use crate::ai_providers::{AIRequest, AIResponse};
+use crate::settings::{AIProviderConfig, CacheSettings};
+use crate::images;
+
+/// Size of the cache as shown in Settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entries: i64,
+    pub size_bytes: i64,
+    pub total_hits: i64,
+}
+
+pub fn get_cache_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+
+    std::fs::create_dir_all(&app_data_dir)
+        .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+
+    Ok(app_data_dir.join("response_cache.db"))
+}
+
+fn open(app_handle: &AppHandle) -> Result<Connection, String> {
+    let conn = Connection::open(get_cache_path(app_handle)?)
+        .map_err(|e| format!("Failed to open response cache: {}", e))?;
+    init(&conn)?;
+    Ok(conn)
+}
+
+fn init(conn: &Connection) -> Result<(), String> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS response_cache (
+            key TEXT PRIMARY KEY,
+            response TEXT NOT NULL,
+            size_bytes INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            last_hit_at TEXT NOT NULL,
+            hits INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    ).map_err(|e| format!("Failed to create response_cache table: {}", e))?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_cache_last_hit ON response_cache (last_hit_at)",
+        [],
+    ).map_err(|e| format!("Failed to create last_hit index: {}", e))?;
+
+    Ok(())
+}
+
+/// Everything that determines the output: the provider endpoint and model with its
+/// options, the rendered conversation, and the sampling parameters. `context` only
+/// carries bookkeeping (the tool name) and is left out, so the same prompt sent from
+/// two tools shares an entry.
+pub fn cache_key(request: &AIRequest, provider: &AIProviderConfig) -> String {
+    // serde_json objects are sorted maps, so this serialization is stable.
+    serde_json::json!({
+        "provider": provider.provider_type,
+        "base_url": provider.base_url,
+        "model": provider.model,
+        "deployment": provider.deployment,
+        "ollama_options": provider.ollama_options,
+        "system_message": request.system_message,
+        "conversation": request.conversation(),
+        "temperature": request.effective_temperature(),
+        "sampling": request.sampling,
+        "candidates": request.candidates,
+        "images": images::fingerprint(&request.images),
+        "tools": request.tools,
+        "tool_choice": request.tool_choice,
+        "max_tokens": request.max_tokens,
+        "response_schema": request.response_schema,
+    }).to_string()
+}
+
+/// A fresh cached response for `key`, marked as `cached`, bumping its hit count.
+pub fn lookup(app_handle: &AppHandle, key: &str, settings: &CacheSettings) -> Result<Option<AIResponse>, String> {
+    lookup_in(&open(app_handle)?, key, settings)
+}
+
+/// Cache a successful response, then evict whatever the limits no longer allow.
+pub fn store(app_handle: &AppHandle, key: &str, response: &AIResponse, settings: &CacheSettings) -> Result<(), String> {
+    let conn = open(app_handle)?;
+    store_in(&conn, key, response)?;
+    prune(&conn, settings)
+}
+
+pub fn stats(app_handle: &AppHandle) -> Result<CacheStats, String> {
+    open(app_handle)?
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size_bytes), 0), COALESCE(SUM(hits), 0) FROM response_cache",
+            [],
+            |row| Ok(CacheStats { entries: row.get(0)?, size_bytes: row.get(1)?, total_hits: row.get(2)? }),
+        )
+        .map_err(|e| format!("Failed to read cache stats: {}", e))
+}
+
+pub fn clear(app_handle: &AppHandle) -> Result<(), String> {
+    open(app_handle)?
+        .execute("DELETE FROM response_cache", [])
+        .map_err(|e| format!("Failed to clear response cache: {}", e))?;
+    Ok(())
+}
+
+fn lookup_in(conn: &Connection, key: &str, settings: &CacheSettings) -> Result<Option<AIResponse>, String> {
+    let oldest = (Utc::now() - Duration::hours(settings.ttl_hours as i64)).to_rfc3339();
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT response FROM response_cache WHERE key = ?1 AND created_at >= ?2",
+            rusqlite::params![key, oldest],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to read response cache: {}", e))?;
+
+    let Some(stored) = stored else {
+        return Ok(None);
+    };
+
+    conn.execute(
+        "UPDATE response_cache SET hits = hits + 1, last_hit_at = ?2 WHERE key = ?1",
+        rusqlite::params![key, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to update response cache: {}", e))?;
+
+    let mut response: AIResponse = serde_json::from_str(&stored)
+        .map_err(|e| format!("Failed to parse cached response: {}", e))?;
+    response.cached = true;
+    Ok(Some(response))
+}
+
+fn store_in(conn: &Connection, key: &str, response: &AIResponse) -> Result<(), String> {
+    let stored = serde_json::to_string(response)
+        .map_err(|e| format!("Failed to serialize response: {}", e))?;
+    let now = Utc::now().to_rfc3339();
+
+    conn.execute(
+        "INSERT OR REPLACE INTO response_cache (key, response, size_bytes, created_at, last_hit_at, hits)
+         VALUES (?1, ?2, ?3, ?4, ?4, 0)",
+        rusqlite::params![key, stored, (key.len() + stored.len()) as i64, now],
+    ).map_err(|e| format!("Failed to write response cache: {}", e))?;
+
+    Ok(())
+}
+
+/// Drop expired entries, then the least recently used ones until both the entry and
+/// the size limit hold.
+fn prune(conn: &Connection, settings: &CacheSettings) -> Result<(), String> {
+    let oldest = (Utc::now() - Duration::hours(settings.ttl_hours as i64)).to_rfc3339();
+    conn.execute("DELETE FROM response_cache WHERE created_at < ?1", [oldest])
+        .map_err(|e| format!("Failed to expire cache entries: {}", e))?;
+
+    let mut stmt = conn.prepare("SELECT key, size_bytes FROM response_cache ORDER BY last_hit_at DESC")
+        .map_err(|e| format!("Failed to prepare cache prune query: {}", e))?;
+    let entries = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))
+        .map_err(|e| format!("Failed to query cache entries: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to collect cache entries: {}", e))?;
+
+    let max_bytes = settings.max_size_mb as i64 * 1024 * 1024;
+    let mut kept_bytes = 0;
+    for (index, (key, size)) in entries.iter().enumerate() {
+        kept_bytes += size;
+        if index >= settings.max_entries as usize || kept_bytes > max_bytes {
+            conn.execute("DELETE FROM response_cache WHERE key = ?1", [key])
+                .map_err(|e| format!("Failed to evict cache entry: {}", e))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_providers::TokenUsage;
+
+    fn request(prompt: &str, temperature: f32) -> AIRequest {
+        AIRequest {
+            prompt: prompt.to_string(),
+            temperature: Some(temperature),
+            max_tokens: Some(500),
+            system_message: Some("You are a consultant.".to_string()),
+            context: None,
+            messages: vec![],
+            response_schema: None,
+            bypass_cache: false,
+            split_input: None,
+            provider: None,
+            model: None,
+            sampling: Default::default(),
+            candidates: None,
+            images: vec![],
+            tools: vec![],
+            tool_choice: Default::default(),
+        }
+    }
+
+    fn provider() -> AIProviderConfig {
+        AIProviderConfig {
+            provider_type: "ollama".to_string(),
+            base_url: "http://localhost:11434".to_string(),
+            model: "llama3.1".to_string(),
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn response(content: &str) -> AIResponse {
+        AIResponse {
+            content: content.to_string(),
+            provider: "ollama".to_string(),
+            model: "llama3.1".to_string(),
+            success: true,
+            error: None,
+            usage: Some(TokenUsage { prompt_tokens: 10, completion_tokens: 90, total_tokens: 100 }),
+            error_type: None,
+            fallbacks: Vec::new(),
+            parsed: None,
+            cached: false,
+            error_details: None,
+            sampling: None,
+            candidates: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_runs: Vec::new(),
+        }
+    }
+
+    fn memory_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_cache_key_covers_parameters() {
+        let base = cache_key(&request("ideas for pets", 0.7), &provider());
+        assert_eq!(base, cache_key(&request("ideas for pets", 0.7), &provider()));
+        assert_ne!(base, cache_key(&request("ideas for cats", 0.7), &provider()));
+        assert_ne!(base, cache_key(&request("ideas for pets", 0.9), &provider()));
+
+        let other_model = AIProviderConfig { model: "mistral".to_string(), ..provider() };
+        assert_ne!(base, cache_key(&request("ideas for pets", 0.7), &other_model));
+
+        // Bookkeeping context doesn't split entries.
+        let mut tagged = request("ideas for pets", 0.7);
+        tagged.context = Some([("tool".to_string(), "idea_forge".to_string())].into());
+        assert_eq!(base, cache_key(&tagged, &provider()));
+    }
+
+    #[test]
+    fn test_store_lookup_and_ttl() {
+        let conn = memory_db();
+        let settings = CacheSettings { enabled: true, ..Default::default() };
+        assert!(lookup_in(&conn, "k", &settings).unwrap().is_none());
+
+        store_in(&conn, "k", &response("three ideas")).unwrap();
+        let hit = lookup_in(&conn, "k", &settings).unwrap().unwrap();
+        assert!(hit.cached);
+        assert_eq!(hit.content, "three ideas");
+        assert_eq!(hit.usage.unwrap().total_tokens, 100);
+
+        conn.execute("UPDATE response_cache SET created_at = ?1", [(Utc::now() - Duration::hours(2)).to_rfc3339()]).unwrap();
+        let short_ttl = CacheSettings { ttl_hours: 1, ..settings };
+        assert!(lookup_in(&conn, "k", &short_ttl).unwrap().is_none());
+        prune(&conn, &short_ttl).unwrap();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM response_cache", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_prune_evicts_least_recently_used() {
+        let conn = memory_db();
+        for key in ["a", "b", "c"] {
+            store_in(&conn, key, &response("answer")).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        // Touch "a" so "b" becomes the least recently used.
+        let settings = CacheSettings { enabled: true, max_entries: 2, ..Default::default() };
+        lookup_in(&conn, "a", &settings).unwrap();
+        prune(&conn, &settings).unwrap();
+
+        let mut stmt = conn.prepare("SELECT key FROM response_cache ORDER BY key").unwrap();
+        let keys: Vec<String> = stmt.query_map([], |row| row.get(0)).unwrap().map(|k| k.unwrap()).collect();
+        assert_eq!(keys, vec!["a", "c"]);
+
+        let tiny = CacheSettings { max_size_mb: 0, ..settings };
+        prune(&conn, &tiny).unwrap();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM response_cache", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -88,7 +88,7 @@ pub fn check_api_key_exists(provider: &str) -> Result<bool, String> {
 
 // Get all API key statuses
 pub fn get_all_api_key_status() -> Result<HashMap<String, ApiKeyInfo>, String> {
-    let providers = vec!["openai", "anthropic", "gemini", "ollama"];
+    let providers = vec!["openai", "anthropic", "gemini", "ollama", "openai_compatible", "azure"];
     let mut status_map = HashMap::new();
     
     for provider in providers {
@@ -121,6 +121,8 @@ fn get_env_var_name(provider: &str) -> String {
         "openai" => "OPENAI_API_KEY".to_string(),
         "anthropic" => "ANTHROPIC_API_KEY".to_string(),
         "gemini" => "GEMINI_API_KEY".to_string(),
+        "openai_compatible" => "OPENAI_COMPATIBLE_API_KEY".to_string(),
+        "azure" => "AZURE_OPENAI_API_KEY".to_string(),
         _ => format!("{}_API_KEY", provider.to_uppercase()),
     }
 }
@@ -204,6 +206,8 @@ mod tests {
         assert_eq!(get_env_var_name("openai"), "OPENAI_API_KEY");
         assert_eq!(get_env_var_name("anthropic"), "ANTHROPIC_API_KEY");
         assert_eq!(get_env_var_name("gemini"), "GEMINI_API_KEY");
+        assert_eq!(get_env_var_name("openai_compatible"), "OPENAI_COMPATIBLE_API_KEY");
+        assert_eq!(get_env_var_name("azure"), "AZURE_OPENAI_API_KEY");
         assert_eq!(get_env_var_name("custom"), "CUSTOM_API_KEY");
     }
 
@@ -1,54 +1,279 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use tauri::Manager;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::error_handling::APIErrorType;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AIProviderConfig {
     pub provider_type: String,
     pub base_url: String,
     pub model: String,
     pub enabled: bool,
+    /// Label shown in the UI; used by "openai_compatible" to name the actual server.
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Azure OpenAI deployment name; falls back to `model` when unset.
+    #[serde(default)]
+    pub deployment: Option<String>,
+    /// Azure OpenAI `api-version` query parameter.
+    #[serde(default)]
+    pub api_version: Option<String>,
+    /// Runtime options sent with every Ollama chat request.
+    #[serde(default)]
+    pub ollama_options: OllamaOptions,
+    /// Behaviour of the offline "mock" provider.
+    #[serde(default)]
+    pub mock_options: MockOptions,
     // Note: API keys are now stored in secure OS keychain, not in this struct
     // For Ollama bearer tokens, we still use keychain for consistency
 }
 
+/// Ollama runtime options. `None` leaves the model's Modelfile default in effect;
+/// see `OllamaProvider::show_model` for reading those defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OllamaOptions {
+    /// Context window in tokens. Ollama's own default is far below most models' maximum.
+    #[serde(default)]
+    pub num_ctx: Option<u32>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub repeat_penalty: Option<f32>,
+    /// How long the model stays loaded after a request, e.g. "5m", "1h", or "-1" to keep it loaded.
+    #[serde(default)]
+    pub keep_alive: Option<String>,
+}
+
+/// Settings for the "mock" provider, which answers without a model or network (see
+/// `mock_provider.rs`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockOptions {
+    /// Delay before each answer.
+    pub latency_ms: u64,
+    /// Delay between streamed words.
+    pub chunk_delay_ms: u64,
+    /// Fail with this error instead of answering.
+    pub fail_with: Option<APIErrorType>,
+    /// Only fail the first this-many attempts of each request, then answer normally, to
+    /// watch the retry loop recover. 0 fails every attempt.
+    pub fail_times: u32,
+    /// `retry_after` (seconds) reported with injected rate limits and other errors.
+    pub retry_after: Option<u64>,
+    /// Response templates by tool ("idea_forge", ...), replacing the built-in ones.
+    /// `{prompt}` and `{model}` are filled in.
+    pub responses: HashMap<String, String>,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        Self {
+            latency_ms: 400,
+            chunk_delay_ms: 20,
+            fail_with: None,
+            fail_times: 0,
+            retry_after: None,
+            responses: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
     pub preferred_provider: String,
+    /// Providers to try, in order, when the preferred one fails with a rate limit, an
+    /// outage, a bad key or an exhausted quota. Disabled entries are skipped.
+    #[serde(default)]
+    pub fallback_providers: Vec<String>,
     pub openai: AIProviderConfig,
     pub anthropic: AIProviderConfig,
     pub gemini: AIProviderConfig,
     pub ollama: AIProviderConfig,
+    #[serde(default = "default_openai_compatible")]
+    pub openai_compatible: AIProviderConfig,
+    #[serde(default = "default_azure")]
+    pub azure: AIProviderConfig,
+    #[serde(default = "default_mock")]
+    pub mock: AIProviderConfig,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    #[serde(default)]
+    pub map_reduce: MapReduceSettings,
+    /// Per-tool provider, model and defaults, keyed by the tool ids in `prompts.rs`.
+    #[serde(default = "default_tool_routing")]
+    pub tool_routing: HashMap<String, ToolRoute>,
+}
+
+/// Where a tool's requests go and what they default to. Anything unset falls back to
+/// the preferred provider, its configured model and the request's own values.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolRoute {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// Used when the request doesn't set its own.
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+/// Every tool on the preferred provider, with the sampling each tool's screen used to set.
+fn default_tool_routing() -> HashMap<String, ToolRoute> {
+    [
+        ("idea_forge", 0.7, 2000),
+        ("global_compass", 0.3, 3000),
+        ("pitch_perfect", 0.3, 3000),
+        ("prd_generator", 0.3, 4000),
+    ]
+    .into_iter()
+    .map(|(tool, temperature, max_tokens)| {
+        (tool.to_string(), ToolRoute { temperature: Some(temperature), max_tokens: Some(max_tokens), ..Default::default() })
+    })
+    .collect()
+}
+
+/// Response cache (see `response_cache.rs`). Off by default: a cached answer to a
+/// creative prompt is the same answer, which is not always what the user wants.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    pub enabled: bool,
+    /// Entries older than this are never served.
+    pub ttl_hours: u32,
+    /// Least recently used entries are evicted beyond either limit.
+    pub max_entries: u32,
+    pub max_size_mb: u32,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_hours: 24 * 7,
+            max_entries: 1000,
+            max_size_mb: 50,
+        }
+    }
+}
+
+/// Splitting of inputs too long for the selected model (see `map_reduce.rs`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MapReduceSettings {
+    /// Keyed by tool id; tools not listed are never split.
+    pub tools: HashMap<String, ToolMapReduce>,
+    /// Most parts one input is split into per round, so a huge paste can't fan out into
+    /// dozens of calls.
+    pub max_chunks: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolMapReduce {
+    pub enabled: bool,
+    /// What each part is condensed to; the tool's built-in instruction if unset.
+    pub instruction: Option<String>,
+}
+
+impl Default for ToolMapReduce {
+    fn default() -> Self {
+        Self { enabled: true, instruction: None }
+    }
+}
+
+impl Default for MapReduceSettings {
+    fn default() -> Self {
+        Self {
+            tools: ["pitch_perfect", "prd_generator"]
+                .into_iter()
+                .map(|tool| (tool.to_string(), ToolMapReduce::default()))
+                .collect(),
+            max_chunks: 12,
+        }
+    }
+}
+
+/// Any server speaking the OpenAI chat completions API. Defaults to LM Studio's port.
+fn default_openai_compatible() -> AIProviderConfig {
+    AIProviderConfig {
+        provider_type: "openai_compatible".to_string(),
+        base_url: "http://localhost:1234/v1".to_string(),
+        model: "local-model".to_string(),
+        enabled: false,
+        display_name: Some("OpenAI-compatible".to_string()),
+        ..Default::default()
+    }
+}
+
+/// GA Azure OpenAI data-plane API version used when the config does not pin one.
+pub const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
+
+/// Azure OpenAI resource endpoint; the deployment and api-version are set separately.
+fn default_azure() -> AIProviderConfig {
+    AIProviderConfig {
+        provider_type: "azure".to_string(),
+        base_url: "https://your-resource.openai.azure.com".to_string(),
+        model: "gpt-4o".to_string(),
+        enabled: false,
+        display_name: Some("Azure OpenAI".to_string()),
+        deployment: None,
+        api_version: Some(DEFAULT_AZURE_API_VERSION.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Offline provider for demos and workshops; makes no network requests.
+fn default_mock() -> AIProviderConfig {
+    AIProviderConfig {
+        provider_type: "mock".to_string(),
+        base_url: String::new(),
+        model: "mock-model".to_string(),
+        enabled: false,
+        display_name: Some("Mock (offline)".to_string()),
+        ..Default::default()
+    }
 }
 
 impl Default for AppSettings {
     fn default() -> Self {
         Self {
             preferred_provider: "ollama".to_string(),
+            fallback_providers: Vec::new(),
             openai: AIProviderConfig {
                 provider_type: "openai".to_string(),
                 base_url: "https://api.openai.com/v1".to_string(),
                 model: "gpt-4".to_string(),
                 enabled: false,
+                ..Default::default()
             },
             anthropic: AIProviderConfig {
                 provider_type: "anthropic".to_string(),
                 base_url: "https://api.anthropic.com".to_string(),
                 model: "claude-3-sonnet-20240229".to_string(),
                 enabled: false,
+                ..Default::default()
             },
             gemini: AIProviderConfig {
                 provider_type: "gemini".to_string(),
                 base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
                 model: "gemini-pro".to_string(),
                 enabled: false,
+                ..Default::default()
             },
             ollama: AIProviderConfig {
                 provider_type: "ollama".to_string(),
                 base_url: "http://localhost:11434".to_string(),
                 model: "llama3.1".to_string(),
                 enabled: true,
+                ..Default::default()
             },
+            openai_compatible: default_openai_compatible(),
+            azure: default_azure(),
+            mock: default_mock(),
+            cache: CacheSettings::default(),
+            map_reduce: MapReduceSettings::default(),
+            tool_routing: default_tool_routing(),
         }
     }
 }
@@ -60,16 +285,67 @@ impl AppSettings {
             "anthropic" => &self.anthropic,
             "gemini" => &self.gemini,
             "ollama" => &self.ollama,
+            "openai_compatible" => &self.openai_compatible,
+            "azure" => &self.azure,
+            "mock" => &self.mock,
             _ => &self.ollama, // Default fallback
         }
     }
 
+    /// The active provider followed by each enabled, distinct fallback provider.
+    pub fn provider_chain(&self) -> Vec<&AIProviderConfig> {
+        let mut chain = vec![self.get_active_provider()];
+        for provider_type in &self.fallback_providers {
+            if let Some(config) = self.get_provider(provider_type) {
+                let seen = chain.iter().any(|c| c.provider_type == config.provider_type);
+                if config.enabled && !seen {
+                    chain.push(config);
+                }
+            }
+        }
+        chain
+    }
+
+    /// `provider_chain` with a request's provider and/or model in place of the preferred
+    /// ones. A provider other than the preferred one must be enabled.
+    pub fn provider_chain_for(&self, provider: Option<&str>, model: Option<&str>) -> Result<Vec<AIProviderConfig>, String> {
+        let chain = self.provider_chain();
+        let mut first = match provider {
+            Some(provider_type) => {
+                let config = self.get_provider(provider_type)
+                    .ok_or_else(|| format!("Unknown provider '{}'", provider_type))?;
+                if !config.enabled && config.provider_type != chain[0].provider_type {
+                    return Err(format!("{} is not enabled in Settings", provider_type));
+                }
+                config.clone()
+            }
+            None => chain[0].clone(),
+        };
+        if let Some(model) = model {
+            if model.trim().is_empty() {
+                return Err("Model override is empty".to_string());
+            }
+            first.model = model.trim().to_string();
+        }
+        first.validate_base_url()?;
+
+        let fallbacks = chain.into_iter()
+            .skip(1)
+            .filter(|c| c.provider_type != first.provider_type)
+            .cloned()
+            .collect::<Vec<_>>();
+        Ok(std::iter::once(first).chain(fallbacks).collect())
+    }
+
     pub fn get_provider(&self, provider_type: &str) -> Option<&AIProviderConfig> {
         match provider_type {
             "openai" => Some(&self.openai),
             "anthropic" => Some(&self.anthropic),
             "gemini" => Some(&self.gemini),
             "ollama" => Some(&self.ollama),
+            "openai_compatible" => Some(&self.openai_compatible),
+            "azure" => Some(&self.azure),
+            "mock" => Some(&self.mock),
             _ => None,
         }
     }
@@ -83,6 +359,9 @@ impl AIProviderConfig {
     /// attacker-controlled or mistyped endpoint from exfiltrating the API key (sent as a
     /// Bearer / x-api-key header) or receiving it over plaintext.
     pub fn validate_base_url(&self) -> Result<(), String> {
+        if self.provider_type == "mock" {
+            return Ok(()); // never contacted
+        }
         let url = self.base_url.trim();
         let lower = url.to_ascii_lowercase();
         let host = host_of(url).to_ascii_lowercase();
@@ -148,7 +427,7 @@ pub async fn load_settings(app_handle: &tauri::AppHandle) -> tauri::Result<AppSe
 
 pub async fn save_settings(app_handle: &tauri::AppHandle, settings: &AppSettings) -> tauri::Result<()> {
     // Validate base URLs before persisting: a bad endpoint would exfiltrate the API key.
-    for cfg in [&settings.openai, &settings.anthropic, &settings.gemini, &settings.ollama] {
+    for cfg in [&settings.openai, &settings.anthropic, &settings.gemini, &settings.ollama, &settings.openai_compatible, &settings.azure, &settings.mock] {
         cfg.validate_base_url().map_err(|e| tauri::Error::Io(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             e,
@@ -192,10 +471,66 @@ mod tests {
         assert_eq!(openai.provider_type, "openai");
         assert_eq!(openai.base_url, "https://api.openai.com/v1");
         
+        let compatible = settings.get_provider("openai_compatible").unwrap();
+        assert_eq!(compatible.provider_type, "openai_compatible");
+        assert!(!compatible.enabled);
+
         let invalid = settings.get_provider("invalid");
         assert!(invalid.is_none());
     }
 
+    #[test]
+    fn test_provider_chain() {
+        let settings = AppSettings {
+            preferred_provider: "openai".to_string(),
+            fallback_providers: vec![
+                "openai".to_string(),    // duplicate of the preferred provider
+                "anthropic".to_string(), // disabled
+                "bogus".to_string(),     // unknown
+                "ollama".to_string(),
+            ],
+            ..AppSettings::default()
+        };
+        let chain: Vec<&str> = settings.provider_chain().iter()
+            .map(|c| c.provider_type.as_str())
+            .collect();
+        assert_eq!(chain, vec!["openai", "ollama"]);
+
+        // Settings files written before fallbacks existed still load.
+        let mut json = serde_json::to_value(AppSettings::default()).unwrap();
+        json.as_object_mut().unwrap().remove("fallback_providers");
+        let loaded: AppSettings = serde_json::from_value(json).unwrap();
+        assert!(loaded.fallback_providers.is_empty());
+        assert_eq!(loaded.provider_chain().len(), 1);
+    }
+
+    #[test]
+    fn test_provider_chain_for_overrides() {
+        let mut settings = AppSettings {
+            preferred_provider: "openai".to_string(),
+            fallback_providers: vec!["anthropic".to_string(), "ollama".to_string()],
+            ..AppSettings::default()
+        };
+        settings.anthropic.enabled = true;
+
+        let chain = settings.provider_chain_for(None, Some("gpt-4o-mini")).unwrap();
+        assert_eq!((chain[0].provider_type.as_str(), chain[0].model.as_str()), ("openai", "gpt-4o-mini"));
+        assert_eq!(chain.len(), 3);
+
+        // The chosen provider leads; the preferred one is not a fallback unless configured.
+        let chain: Vec<String> = settings.provider_chain_for(Some("anthropic"), None).unwrap()
+            .into_iter()
+            .map(|c| c.provider_type)
+            .collect();
+        assert_eq!(chain, vec!["anthropic", "ollama"]);
+
+        assert!(settings.provider_chain_for(Some("gemini"), None).unwrap_err().contains("not enabled"));
+        assert!(settings.provider_chain_for(Some("bogus"), None).is_err());
+        assert!(settings.provider_chain_for(None, Some("  ")).is_err());
+        settings.anthropic.base_url = "http://api.anthropic.com".to_string();
+        assert!(settings.provider_chain_for(Some("anthropic"), None).is_err());
+    }
+
     #[test]
     fn test_serialization() {
         let settings = AppSettings::default();
@@ -210,6 +545,7 @@ mod tests {
             base_url: base_url.to_string(),
             model: "m".to_string(),
             enabled: true,
+            ..Default::default()
         }
     }
 
@@ -0,0 +1,236 @@
+use serde_json::Value;
+
+use crate::ai_providers::AIResponse;
+use crate::error_handling::{APIError, APIErrorType};
+
+/// Parse a model's JSON answer. Native JSON modes return bare JSON, but OpenAI-compatible
+/// servers without schema support tend to wrap it in a markdown code fence.
+pub fn parse_json(content: &str) -> Result<Value, String> {
+    let trimmed = content.trim();
+    let unfenced = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(trimmed);
+
+    serde_json::from_str(unfenced.trim()).map_err(|e| format!("Response is not valid JSON: {}", e))
+}
+
+/// Check `value` against `schema`, returning one message per violation (empty if valid).
+///
+/// Covers the keywords used for describing model output: `type`, `enum`, `const`,
+/// `properties`, `required`, `additionalProperties`, `items`, `anyOf`/`oneOf`, and the
+/// length/size/range bounds. Anything else (`$ref`, `format`, `pattern`, ...) is accepted.
+pub fn validate(value: &Value, schema: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(value, schema, "$", &mut errors);
+    errors
+}
+
+fn validate_at(value: &Value, schema: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        // `true`, `{}` and anything unrecognised accept every value
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: no value is allowed here", path));
+        }
+        return;
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value)));
+            return;
+        }
+    }
+
+    if let Some(options) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !options.contains(value) {
+            errors.push(format!("{}: {} is not one of the allowed values", path, value));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            errors.push(format!("{}: expected {}", path, constant));
+        }
+    }
+
+    for key in ["anyOf", "oneOf"] {
+        if let Some(branches) = schema.get(key).and_then(|b| b.as_array()) {
+            if !branches.iter().any(|branch| validate(value, branch).is_empty()) {
+                errors.push(format!("{}: does not match any allowed shape", path));
+            }
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+                for name in required.iter().filter_map(|n| n.as_str()) {
+                    if !object.contains_key(name) {
+                        errors.push(format!("{}: missing required property '{}'", path, name));
+                    }
+                }
+            }
+            for (name, child) in object {
+                let child_path = format!("{}.{}", path, name);
+                match properties.and_then(|p| p.get(name)) {
+                    Some(child_schema) => validate_at(child, child_schema, &child_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{}: unexpected property '{}'", path, name));
+                        }
+                        Some(extra) => validate_at(child, extra, &child_path, errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            check_bound(items.len() as f64, schema, "minItems", "maxItems", "item count", path, errors);
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(item, item_schema, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        Value::String(s) => {
+            check_bound(s.chars().count() as f64, schema, "minLength", "maxLength", "length", path, errors);
+        }
+        Value::Number(n) => {
+            check_bound(n.as_f64().unwrap_or(0.0), schema, "minimum", "maximum", "value", path, errors);
+        }
+        _ => {}
+    }
+}
+
+fn check_bound(actual: f64, schema: &serde_json::Map<String, Value>, min_key: &str, max_key: &str, what: &str, path: &str, errors: &mut Vec<String>) {
+    if let Some(min) = schema.get(min_key).and_then(|m| m.as_f64()) {
+        if actual < min {
+            errors.push(format!("{}: {} {} is below the minimum of {}", path, what, actual, min));
+        }
+    }
+    if let Some(max) = schema.get(max_key).and_then(|m| m.as_f64()) {
+        if actual > max {
+            errors.push(format!("{}: {} {} is above the maximum of {}", path, what, actual, max));
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// `content` parsed, if it matches `schema`; otherwise what is wrong with it.
+pub fn check(content: &str, schema: &Value) -> Result<Value, String> {
+    let value = parse_json(content)?;
+    let errors = validate(&value, schema);
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// Parse and validate a successful response's `content`, storing the result in `parsed`.
+/// Output that isn't valid JSON, or doesn't match the schema, fails the response with
+/// `InvalidRequest`; the raw text stays in `content` for inspection.
+pub fn attach(response: &mut AIResponse, schema: &Value) {
+    if !response.success {
+        return;
+    }
+
+    let errors = match check(&response.content, schema) {
+        Ok(value) => {
+            response.parsed = Some(value);
+            return;
+        }
+        Err(errors) => errors,
+    };
+
+    let error = APIError::new(
+        APIErrorType::InvalidRequest,
+        &response.provider,
+        format!("Response does not match the requested schema: {}", errors),
+    ).with_code("schema_mismatch");
+    response.success = false;
+    response.error = Some(error.to_user_message());
+    response.error_type = Some(error.error_type.clone());
+    response.error_details = Some(error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ideas_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "ideas": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": {"type": "string", "minLength": 1},
+                            "description": {"type": "string"},
+                            "market": {"enum": ["b2b", "b2c"]}
+                        },
+                        "required": ["name", "description"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["ideas"]
+        })
+    }
+
+    #[test]
+    fn test_parse_json_strips_code_fence() {
+        assert_eq!(parse_json("```json\n{\"a\": 1}\n```").unwrap(), json!({"a": 1}));
+        assert_eq!(parse_json("  [1, 2]  ").unwrap(), json!([1, 2]));
+        assert!(parse_json("Here are your ideas: ...").is_err());
+    }
+
+    #[test]
+    fn test_validate_accepts_matching_value() {
+        let value = json!({"ideas": [{"name": "Acme", "description": "Rockets", "market": "b2b"}]});
+        assert!(validate(&value, &ideas_schema()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_reports_paths() {
+        let value = json!({"ideas": [{"name": "", "market": "b2g", "extra": true}]});
+        let errors = validate(&value, &ideas_schema());
+        assert!(errors.iter().any(|e| e.contains("$.ideas[0]: missing required property 'description'")));
+        assert!(errors.iter().any(|e| e.contains("$.ideas[0].name") && e.contains("minimum")));
+        assert!(errors.iter().any(|e| e.starts_with("$.ideas[0].market")));
+        assert!(errors.iter().any(|e| e.contains("unexpected property 'extra'")));
+
+        assert_eq!(validate(&json!({"ideas": "none"}), &ideas_schema()), vec!["$.ideas: expected array, got string"]);
+        assert!(validate(&json!(3), &json!({"type": "integer"})).is_empty());
+        assert!(!validate(&json!(3.5), &json!({"type": "integer"})).is_empty());
+    }
+}
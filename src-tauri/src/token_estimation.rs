@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+
+use crate::ai_providers::{AIRequest, ChatMessage, ChatRole};
+use crate::error_handling::{APIError, APIErrorType};
+use crate::model_capabilities::{CapabilitySource, ModelCapabilities};
+
+/// Output budget assumed when a request doesn't set `max_tokens`, as in the providers.
+pub const DEFAULT_MAX_TOKENS: u32 = 2000;
+
+/// Chat formatting overhead per message, and for priming the reply (OpenAI's figures;
+/// other providers' templates are in the same range).
+const TOKENS_PER_MESSAGE: u32 = 4;
+const REPLY_PRIMING_TOKENS: u32 = 3;
+
+/// Per attached image, without decoding it for its size: a slide-sized image costs 765
+/// tokens at OpenAI and up to about 1600 at Anthropic.
+const TOKENS_PER_IMAGE: u32 = 1000;
+
+/// How the prompt was counted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EstimationMethod {
+    /// tiktoken's `cl100k_base`: GPT-4, GPT-3.5.
+    Cl100kBase,
+    /// tiktoken's `o200k_base`: GPT-4o and later.
+    O200kBase,
+    /// Characters per token, for models whose tokenizer isn't public or bundled.
+    Heuristic,
+}
+
+/// Pre-flight size of a request against the model it is about to be sent to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenEstimate {
+    pub prompt_tokens: u32,
+    /// `max_tokens` of the request, or the default the providers use.
+    pub max_output_tokens: u32,
+    pub context_window: u32,
+    pub method: EstimationMethod,
+    /// Where `context_window` came from; `Default` means the limit is a guess.
+    pub capabilities_source: CapabilitySource,
+    /// The prompt plus the requested output fit in the context window.
+    pub fits: bool,
+    pub warning: Option<String>,
+}
+
+impl TokenEstimate {
+    /// Refuse a request whose prompt alone can't fit, unless the limit is only a guess.
+    /// A prompt that fits but leaves less room than `max_tokens` only gets a warning,
+    /// since the answer is merely at risk of being cut short.
+    pub fn guard(&self, provider: &str, model: &str) -> Result<(), APIError> {
+        if self.prompt_tokens < self.context_window || self.capabilities_source == CapabilitySource::Default {
+            return Ok(());
+        }
+        Err(APIError::new(
+            APIErrorType::InvalidRequest,
+            provider,
+            too_long(self.prompt_tokens, model, self.context_window),
+        ).with_code("context_length_exceeded"))
+    }
+}
+
+fn too_long(prompt_tokens: u32, model: &str, context_window: u32) -> String {
+    format!(
+        "This request is about {} tokens, but {} accepts at most {}. Shorten the input or choose a model with a larger context window.",
+        prompt_tokens, model, context_window
+    )
+}
+
+/// Which tokenizer counts for `model`. OpenAI models use their published BPE encodings;
+/// gateways are trusted when the model name is a known OpenAI one.
+pub fn method_for(provider_type: &str, model: &str) -> EstimationMethod {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let method = match get_tokenizer(name) {
+        Some(Tokenizer::O200kBase | Tokenizer::O200kHarmony) => Some(EstimationMethod::O200kBase),
+        Some(_) => Some(EstimationMethod::Cl100kBase),
+        None => None,
+    };
+    match provider_type {
+        // Unrecognised OpenAI names are newer models, which all use o200k.
+        "openai" | "azure" => method.unwrap_or(EstimationMethod::O200kBase),
+        "openai_compatible" => method.unwrap_or(EstimationMethod::Heuristic),
+        _ => EstimationMethod::Heuristic,
+    }
+}
+
+pub fn count_tokens(text: &str, method: EstimationMethod) -> u32 {
+    let tokens = match method {
+        EstimationMethod::Cl100kBase => tiktoken_rs::cl100k_base_singleton().encode_ordinary(text).len(),
+        EstimationMethod::O200kBase => tiktoken_rs::o200k_base_singleton().encode_ordinary(text).len(),
+        // About four characters per token for English; scripts outside ASCII (CJK in
+        // particular) run closer to one token per character, so count those one each.
+        EstimationMethod::Heuristic => {
+            let ascii = text.bytes().filter(|b| b.is_ascii()).count();
+            let other = text.chars().filter(|c| !c.is_ascii()).count();
+            ascii.div_ceil(4) + other
+        }
+    };
+    tokens as u32
+}
+
+/// Estimate `request` for `model` with the given limits.
+pub fn estimate(request: &AIRequest, provider_type: &str, model: &str, capabilities: &ModelCapabilities) -> TokenEstimate {
+    let method = method_for(provider_type, model);
+    let system = request.system_message.iter().map(|content| ChatMessage {
+        role: ChatRole::System,
+        content: content.clone(),
+        tool_calls: vec![],
+    });
+    let mut prompt_tokens = REPLY_PRIMING_TOKENS;
+    for message in system.chain(request.conversation()) {
+        prompt_tokens += TOKENS_PER_MESSAGE + count_tokens(&message.content, method);
+        for call in &message.tool_calls {
+            prompt_tokens += count_tokens(&call.name, method) + count_tokens(&call.arguments.to_string(), method);
+        }
+    }
+    if let Some(schema) = &request.response_schema {
+        prompt_tokens += count_tokens(&schema.to_string(), method);
+    }
+    if !request.tools.is_empty() {
+        prompt_tokens += count_tokens(&serde_json::to_string(&request.tools).unwrap_or_default(), method);
+    }
+    prompt_tokens += TOKENS_PER_IMAGE * request.images.len() as u32;
+
+    let max_output_tokens = request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+    let context_window = capabilities.context_window;
+    let fits = prompt_tokens.saturating_add(max_output_tokens) <= context_window;
+    let room = context_window.saturating_sub(prompt_tokens);
+    let known = capabilities.source != CapabilitySource::Default;
+
+    let warning = if prompt_tokens >= context_window && !known {
+        Some(format!(
+            "This request is about {} tokens, which may exceed {}'s context window. Its limit is unknown; set it under model capabilities if the request fails.",
+            prompt_tokens, model
+        ))
+    } else if prompt_tokens >= context_window {
+        Some(too_long(prompt_tokens, model, context_window))
+    } else if !fits {
+        Some(format!(
+            "Only about {} tokens are left for the answer, fewer than the {} requested, so it may be cut short. Shorten the input or lower the maximum length.",
+            room, max_output_tokens
+        ))
+    } else {
+        None
+    };
+
+    TokenEstimate {
+        prompt_tokens,
+        max_output_tokens,
+        context_window,
+        method,
+        capabilities_source: capabilities.source,
+        fits,
+        warning,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(prompt: &str, max_tokens: Option<u32>) -> AIRequest {
+        AIRequest {
+            prompt: prompt.to_string(),
+            temperature: Some(0.7),
+            max_tokens,
+            system_message: Some("You are a pitch coach.".to_string()),
+            context: None,
+            messages: vec![],
+            response_schema: None,
+            bypass_cache: false,
+            split_input: None,
+            provider: None,
+            model: None,
+            sampling: Default::default(),
+            candidates: None,
+            images: vec![],
+            tools: vec![],
+            tool_choice: Default::default(),
+        }
+    }
+
+    fn capabilities(context_window: u32, source: CapabilitySource) -> ModelCapabilities {
+        ModelCapabilities { context_window, source, ..Default::default() }
+    }
+
+    #[test]
+    fn test_methods_and_counts() {
+        assert_eq!(method_for("openai", "gpt-4o-mini"), EstimationMethod::O200kBase);
+        assert_eq!(method_for("openai", "gpt-4"), EstimationMethod::Cl100kBase);
+        assert_eq!(method_for("azure", "some-future-model"), EstimationMethod::O200kBase);
+        assert_eq!(method_for("openai_compatible", "openai/gpt-4o"), EstimationMethod::O200kBase);
+        assert_eq!(method_for("openai_compatible", "meta-llama/llama-3.1-70b"), EstimationMethod::Heuristic);
+        assert_eq!(method_for("anthropic", "claude-sonnet-4"), EstimationMethod::Heuristic);
+
+        assert_eq!(count_tokens("hello world", EstimationMethod::Cl100kBase), 2);
+        assert_eq!(count_tokens("hello world", EstimationMethod::O200kBase), 2);
+        assert_eq!(count_tokens("hello world", EstimationMethod::Heuristic), 3);
+        assert_eq!(count_tokens("日本語", EstimationMethod::Heuristic), 3);
+    }
+
+    #[test]
+    fn test_estimate_and_guard() {
+        // "You are a pitch coach." and "hello world" under o200k, plus chat overhead.
+        let small = estimate(&request("hello world", Some(100)), "openai", "gpt-4o", &capabilities(128_000, CapabilitySource::Bundled));
+        assert_eq!(small.prompt_tokens, 3 + (4 + 6) + (4 + 2));
+        assert!(small.fits && small.warning.is_none());
+        assert!(small.guard("openai", "gpt-4o").is_ok());
+
+        let transcript = "We help bakeries cut waste. ".repeat(400);
+        let tight = estimate(&request(&transcript, None), "anthropic", "claude-3-haiku", &capabilities(4_000, CapabilitySource::Bundled));
+        assert!(!tight.fits);
+        assert!(tight.warning.as_deref().unwrap().contains("cut short"));
+        assert!(tight.guard("anthropic", "claude-3-haiku").is_ok());
+
+        let too_big = estimate(&request(&transcript.repeat(4), None), "anthropic", "claude-3-haiku", &capabilities(4_000, CapabilitySource::Bundled));
+        let error = too_big.guard("anthropic", "claude-3-haiku").unwrap_err();
+        assert_eq!(error.code, "context_length_exceeded");
+        assert_eq!(error.error_type, APIErrorType::InvalidRequest);
+
+        // An unknown limit never blocks the request.
+        let guessed = estimate(&request(&transcript.repeat(4), None), "ollama", "my-model", &capabilities(4_096, CapabilitySource::Default));
+        assert!(guessed.guard("ollama", "my-model").is_ok());
+        assert!(guessed.warning.as_deref().unwrap().contains("unknown"));
+    }
+}
@@ -0,0 +1,364 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::ai_providers::{AIRequest, AIResponse, ChatMessage, ChatRole};
+
+/// Output of one of the built-in tools, requested from the model as JSON.
+pub trait ToolResult: Serialize + DeserializeOwned {
+    /// Id of the tool (and of its prompt in `prompts.rs`).
+    const TOOL_ID: &'static str;
+    /// JSON Schema sent as the request's `response_schema`.
+    fn schema() -> Value;
+}
+
+/// A typed tool result together with the response it was parsed from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolResponse<T> {
+    /// `None` if the output still could not be parsed after the repair attempts.
+    pub result: Option<T>,
+    pub response: AIResponse,
+    /// How many times the model was asked to fix its output.
+    pub repair_attempts: u32,
+}
+
+/// Idea Forge: the generated ideas.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdeaForgeResult {
+    pub ideas: Vec<BusinessIdea>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusinessIdea {
+    pub name: String,
+    pub description: String,
+    pub target_market: String,
+    pub value_proposition: String,
+    pub challenge: String,
+}
+
+/// Global Compass: a market entry analysis, one field per section of the prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketAnalysis {
+    pub market_opportunity: String,
+    pub cultural_considerations: String,
+    pub regulatory_requirements: String,
+    pub competitive_landscape: String,
+    pub entry_strategy: Vec<String>,
+    pub risks: Vec<String>,
+    pub success_factors: Vec<String>,
+    pub kpis: Vec<String>,
+}
+
+/// Pitch Perfect: scores and feedback for a pitch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PitchScorecard {
+    pub scores: PitchScores,
+    pub strengths: Vec<String>,
+    pub improvements: Vec<String>,
+    pub suggestions: Vec<String>,
+    pub rewrite_suggestions: Vec<String>,
+}
+
+/// Scores from 1 to 10.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PitchScores {
+    pub clarity: u8,
+    pub persuasiveness: u8,
+    pub audience_fit: u8,
+    pub call_to_action: u8,
+}
+
+/// PRD Generator: the document's sections, each as markdown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrdDocument {
+    pub feature_name: String,
+    pub executive_summary: String,
+    pub problem_statement: String,
+    pub goals_and_metrics: String,
+    pub user_stories: String,
+    pub functional_requirements: String,
+    pub non_functional_requirements: String,
+    pub technical_specifications: String,
+    pub design_guidelines: String,
+    pub implementation_plan: String,
+    pub risk_assessment: String,
+    pub acceptance_criteria: String,
+}
+
+impl PrdDocument {
+    /// The document as markdown, in the section order the prompt asks for.
+    pub fn to_markdown(&self) -> String {
+        let sections = [
+            ("Executive Summary", &self.executive_summary),
+            ("Problem Statement", &self.problem_statement),
+            ("Goals & Success Metrics", &self.goals_and_metrics),
+            ("User Stories & Use Cases", &self.user_stories),
+            ("Functional Requirements", &self.functional_requirements),
+            ("Non-Functional Requirements", &self.non_functional_requirements),
+            ("Technical Specifications", &self.technical_specifications),
+            ("Design Guidelines", &self.design_guidelines),
+            ("Implementation Plan", &self.implementation_plan),
+            ("Risk Assessment", &self.risk_assessment),
+            ("Acceptance Criteria", &self.acceptance_criteria),
+        ];
+
+        let mut markdown = format!("# {}\n", self.feature_name);
+        for (i, (title, body)) in sections.iter().enumerate() {
+            markdown.push_str(&format!("\n## {}. {}\n\n{}\n", i + 1, title, body.trim()));
+        }
+        markdown
+    }
+}
+
+// Schema helpers. Every property is required and no others are allowed, which is also
+// what OpenAI's strict mode expects.
+fn object(properties: &[(&str, Value)]) -> Value {
+    let names: Vec<&str> = properties.iter().map(|(name, _)| *name).collect();
+    let properties: serde_json::Map<String, Value> = properties.iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": names,
+        "additionalProperties": false
+    })
+}
+
+fn text(description: &str) -> Value {
+    json!({"type": "string", "description": description})
+}
+
+fn list(description: &str) -> Value {
+    json!({"type": "array", "description": description, "items": {"type": "string"}})
+}
+
+fn score(description: &str) -> Value {
+    json!({"type": "integer", "minimum": 1, "maximum": 10, "description": description})
+}
+
+impl ToolResult for IdeaForgeResult {
+    const TOOL_ID: &'static str = "idea_forge";
+
+    fn schema() -> Value {
+        let idea = object(&[
+            ("name", text("Catchy business name")),
+            ("description", text("2-3 sentence description")),
+            ("target_market", text("Who the customers are")),
+            ("value_proposition", text("Key value proposition")),
+            ("challenge", text("One potential challenge")),
+        ]);
+        object(&[("ideas", json!({"type": "array", "minItems": 1, "items": idea}))])
+    }
+}
+
+impl ToolResult for MarketAnalysis {
+    const TOOL_ID: &'static str = "global_compass";
+
+    fn schema() -> Value {
+        object(&[
+            ("market_opportunity", text("Market opportunity and size")),
+            ("cultural_considerations", text("Cultural considerations and business practices")),
+            ("regulatory_requirements", text("Regulatory and legal requirements")),
+            ("competitive_landscape", text("Competitive landscape")),
+            ("entry_strategy", list("Entry strategy recommendations, in order")),
+            ("risks", list("Main risks")),
+            ("success_factors", list("Success factors")),
+            ("kpis", list("KPIs to track")),
+        ])
+    }
+}
+
+impl ToolResult for PitchScorecard {
+    const TOOL_ID: &'static str = "pitch_perfect";
+
+    fn schema() -> Value {
+        object(&[
+            ("scores", object(&[
+                ("clarity", score("Clarity & structure")),
+                ("persuasiveness", score("Persuasiveness")),
+                ("audience_fit", score("Fit with the target audience")),
+                ("call_to_action", score("Clarity of the call to action")),
+            ])),
+            ("strengths", list("What works well")),
+            ("improvements", list("Areas for improvement")),
+            ("suggestions", list("Concrete, actionable suggestions")),
+            ("rewrite_suggestions", list("Improved wording for the opening and closing")),
+        ])
+    }
+}
+
+impl ToolResult for PrdDocument {
+    const TOOL_ID: &'static str = "prd_generator";
+
+    fn schema() -> Value {
+        object(&[
+            ("feature_name", text("Name of the feature")),
+            ("executive_summary", text("Markdown: overview and business rationale")),
+            ("problem_statement", text("Markdown: the problem being solved")),
+            ("goals_and_metrics", text("Markdown: goals and success metrics")),
+            ("user_stories", text("Markdown: user stories and use cases")),
+            ("functional_requirements", text("Markdown: features and capabilities")),
+            ("non_functional_requirements", text("Markdown: performance, security, scalability")),
+            ("technical_specifications", text("Markdown: architecture, data models, integrations")),
+            ("design_guidelines", text("Markdown: UI/UX requirements")),
+            ("implementation_plan", text("Markdown: phases and timeline")),
+            ("risk_assessment", text("Markdown: risks and mitigations")),
+            ("acceptance_criteria", text("Markdown: definition of done")),
+        ])
+    }
+}
+
+/// Prepare a tool request: ask for `T`'s schema and tag the request with the tool id
+/// (used for usage tracking) unless the caller already did.
+pub fn prepare_request<T: ToolResult>(mut request: AIRequest) -> AIRequest {
+    request.response_schema = Some(T::schema());
+    request.context
+        .get_or_insert_with(Default::default)
+        .entry("tool".to_string())
+        .or_insert_with(|| T::TOOL_ID.to_string());
+    request
+}
+
+/// Read `T` out of a response, or say why it can't be.
+pub fn parse<T: ToolResult>(response: &AIResponse) -> Result<T, String> {
+    match &response.parsed {
+        Some(parsed) => serde_json::from_value(parsed.clone())
+            .map_err(|e| format!("Failed to read {} result: {}", T::TOOL_ID, e)),
+        // The raw message; `error` is worded for the UI.
+        None => Err(response.error_details.as_ref()
+            .map(|e| e.message.clone())
+            .or_else(|| response.error.clone())
+            .unwrap_or_else(|| "Response contained no JSON".to_string())),
+    }
+}
+
+/// Whether a failed parse is worth a repair round: the model produced output, it just
+/// wasn't usable. Provider errors leave `content` empty and are not repairable.
+pub fn is_repairable(response: &AIResponse) -> bool {
+    !response.content.trim().is_empty()
+}
+
+/// Follow-up request that shows the model its unusable answer and the problem with it.
+pub fn repair_request(request: &AIRequest, response: &AIResponse, problem: &str) -> AIRequest {
+    let mut messages = request.conversation();
+    messages.push(ChatMessage { role: ChatRole::Assistant, content: response.content.clone(), tool_calls: vec![] });
+
+    AIRequest {
+        prompt: format!(
+            "Your previous answer could not be used: {}\n\nReply again with only a JSON object that matches the required schema. Keep the content; fix the structure.",
+            problem
+        ),
+        messages,
+        // The history holds the whole prompt, so this can't be split the same way.
+        split_input: None,
+        // None of the candidates matched; repair a single answer.
+        candidates: None,
+        // The answer to fix is text; the images and functions needn't be sent again.
+        images: vec![],
+        tools: vec![],
+        ..request.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structured_output;
+
+    fn response(content: &str, parsed: Option<Value>) -> AIResponse {
+        AIResponse {
+            content: content.to_string(),
+            provider: "ollama".to_string(),
+            model: "llama3.1".to_string(),
+            success: parsed.is_some(),
+            error: None,
+            usage: None,
+            error_type: None,
+            fallbacks: Vec::new(),
+            parsed,
+            cached: false,
+            error_details: None,
+            sampling: None,
+            candidates: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_runs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_schemas_accept_sample_output() {
+        let scorecard = json!({
+            "scores": {"clarity": 8, "persuasiveness": 6, "audience_fit": 7, "call_to_action": 4},
+            "strengths": ["Clear problem"],
+            "improvements": ["Vague ask"],
+            "suggestions": ["State the amount"],
+            "rewrite_suggestions": ["Open with the customer story"]
+        });
+        assert!(structured_output::validate(&scorecard, &PitchScorecard::schema()).is_empty());
+        let parsed = parse::<PitchScorecard>(&response("", Some(scorecard))).unwrap();
+        assert_eq!(parsed.scores.call_to_action, 4);
+
+        let out_of_range = json!({"clarity": 11, "persuasiveness": 6, "audience_fit": 7, "call_to_action": 4});
+        let score_schema = &PitchScorecard::schema()["properties"]["scores"];
+        assert!(!structured_output::validate(&out_of_range, score_schema).is_empty());
+
+        let ideas = json!({"ideas": [{
+            "name": "Acme", "description": "d", "target_market": "t",
+            "value_proposition": "v", "challenge": "c"
+        }]});
+        assert!(structured_output::validate(&ideas, &IdeaForgeResult::schema()).is_empty());
+        assert!(!structured_output::validate(&json!({"ideas": []}), &IdeaForgeResult::schema()).is_empty());
+    }
+
+    #[test]
+    fn test_prepare_and_repair_requests() {
+        let request = prepare_request::<MarketAnalysis>(AIRequest {
+            prompt: "Analyze Germany".to_string(),
+            temperature: Some(0.7),
+            max_tokens: None,
+            system_message: None,
+            context: None,
+            messages: vec![],
+            response_schema: None,
+            bypass_cache: false,
+            split_input: None,
+            provider: None,
+            model: None,
+            sampling: Default::default(),
+            candidates: None,
+            images: vec![],
+            tools: vec![],
+            tool_choice: Default::default(),
+        });
+        assert_eq!(request.context.as_ref().unwrap()["tool"], "global_compass");
+        assert!(request.response_schema.is_some());
+
+        let bad = response("1. Market Opportunity: large", None);
+        assert!(is_repairable(&bad));
+        assert!(!is_repairable(&response("", None)));
+
+        let repair = repair_request(&request, &bad, "Response is not valid JSON");
+        let roles: Vec<ChatRole> = repair.messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![ChatRole::User, ChatRole::Assistant]);
+        assert!(repair.prompt.contains("Response is not valid JSON"));
+        assert_eq!(repair.response_schema, request.response_schema);
+    }
+
+    #[test]
+    fn test_prd_markdown_sections() {
+        let mut prd: PrdDocument = serde_json::from_value(json!({
+            "feature_name": "Profiles", "executive_summary": "Summary", "problem_statement": "",
+            "goals_and_metrics": "", "user_stories": "", "functional_requirements": "",
+            "non_functional_requirements": "", "technical_specifications": "",
+            "design_guidelines": "", "implementation_plan": "", "risk_assessment": "",
+            "acceptance_criteria": "Done when saved"
+        })).unwrap();
+        prd.problem_statement = "Users can't edit profiles".to_string();
+        let markdown = prd.to_markdown();
+        assert!(markdown.starts_with("# Profiles\n"));
+        assert!(markdown.contains("## 2. Problem Statement\n\nUsers can't edit profiles\n"));
+        assert!(markdown.contains("## 11. Acceptance Criteria"));
+    }
+}
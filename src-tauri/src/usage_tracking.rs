@@ -2,8 +2,23 @@ use chrono::{DateTime, Utc};
 use rusqlite::{Connection, Result};
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+use crate::ai_providers::AIRequest;
+
+/// Request context keys linking the usage records of the calls one request fans out
+/// into (map-reduce passes, candidate ranking).
+pub const USAGE_GROUP_KEY: &str = "usage_group";
+pub const USAGE_STAGE_KEY: &str = "usage_stage";
+
+/// Mark `request` as the `stage` call of the request `group`.
+pub fn tag(request: &mut AIRequest, group: &str, stage: &str) {
+    let context = request.context.get_or_insert_with(HashMap::new);
+    context.insert(USAGE_GROUP_KEY.to_string(), group.to_string());
+    context.insert(USAGE_STAGE_KEY.to_string(), stage.to_string());
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsageRecord {
     pub id: Option<i64>,
@@ -17,6 +32,48 @@ pub struct UsageRecord {
     pub success: bool,
     pub error_message: Option<String>,
     pub response_time_ms: i64,
+    pub status: UsageStatus,
+    /// For cache hits: the tokens the original generation used, i.e. what the hit saved.
+    #[serde(default)]
+    pub saved_tokens: i32,
+    /// Shared by the calls one request fans out into (see `tag`).
+    #[serde(default)]
+    pub group_id: Option<String>,
+    /// Which of those calls this was, e.g. "map 2/5", "reduce" or "rank".
+    #[serde(default)]
+    pub stage: Option<String>,
+}
+
+/// Outcome of a recorded request. Cancelled requests are kept apart from failures so
+/// the Usage page doesn't count a user pressing "Stop" as a provider error; cache hits
+/// are successful but cost nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageStatus {
+    Success,
+    Failed,
+    Cancelled,
+    Cached,
+}
+
+impl UsageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageStatus::Success => "success",
+            UsageStatus::Failed => "failed",
+            UsageStatus::Cancelled => "cancelled",
+            UsageStatus::Cached => "cached",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "success" => UsageStatus::Success,
+            "cancelled" => UsageStatus::Cancelled,
+            "cached" => UsageStatus::Cached,
+            _ => UsageStatus::Failed,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +84,9 @@ pub struct UsageStats {
     pub total_output_tokens: i64,
     pub successful_requests: i64,
     pub failed_requests: i64,
+    pub cancelled_requests: i64,
+    pub cached_requests: i64,
+    pub tokens_saved: i64,
     pub average_response_time_ms: f64,
     pub by_provider: Vec<ProviderStats>,
     pub by_tool: Vec<ToolStats>,
@@ -82,6 +142,18 @@ pub fn init_database(app_handle: &AppHandle) -> Result<(), String> {
         )",
         [],
     ).map_err(|e| format!("Failed to create usage_records table: {}", e))?;
+
+    // Databases created before these columns existed: add them, and backfill the status
+    // from `success`.
+    if ensure_column(&conn, "status", "TEXT")? {
+        conn.execute(
+            "UPDATE usage_records SET status = CASE WHEN success = 1 THEN 'success' ELSE 'failed' END",
+            [],
+        ).map_err(|e| format!("Failed to backfill status column: {}", e))?;
+    }
+    ensure_column(&conn, "saved_tokens", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(&conn, "group_id", "TEXT")?;
+    ensure_column(&conn, "stage", "TEXT")?;
     
     // Create indexes for better query performance
     conn.execute(
@@ -102,6 +174,19 @@ pub fn init_database(app_handle: &AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// Add `name` to usage_records unless it's there already. Returns whether it was added.
+fn ensure_column(conn: &Connection, name: &str, ddl: &str) -> Result<bool, String> {
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info('usage_records') WHERE name = ?1")
+        .and_then(|mut stmt| stmt.exists([name]))
+        .map_err(|e| format!("Failed to inspect usage_records table: {}", e))?;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE usage_records ADD COLUMN {} {}", name, ddl), [])
+            .map_err(|e| format!("Failed to add {} column: {}", name, e))?;
+    }
+    Ok(!exists)
+}
+
 pub async fn record_usage(app_handle: &AppHandle, record: UsageRecord) -> Result<(), String> {
     let db_path = get_database_path(app_handle)?;
     let conn = Connection::open(&db_path)
@@ -110,8 +195,9 @@ pub async fn record_usage(app_handle: &AppHandle, record: UsageRecord) -> Result
     conn.execute(
         "INSERT INTO usage_records (
             timestamp, provider, model, tool, input_tokens, output_tokens, 
-            total_tokens, success, error_message, response_time_ms
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            total_tokens, success, error_message, response_time_ms, status, saved_tokens,
+            group_id, stage
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
         rusqlite::params![
             record.timestamp.to_rfc3339(),
             record.provider,
@@ -123,6 +209,10 @@ pub async fn record_usage(app_handle: &AppHandle, record: UsageRecord) -> Result
             record.success,
             record.error_message,
             record.response_time_ms,
+            record.status.as_str(),
+            record.saved_tokens,
+            record.group_id,
+            record.stage,
         ],
     ).map_err(|e| format!("Failed to insert usage record: {}", e))?;
     
@@ -147,8 +237,11 @@ pub async fn get_usage_stats(app_handle: &AppHandle, days: Option<i32>) -> Resul
             COALESCE(SUM(total_tokens), 0) as total_tokens,
             COALESCE(SUM(input_tokens), 0) as total_input_tokens,
             COALESCE(SUM(output_tokens), 0) as total_output_tokens,
-            COALESCE(SUM(CASE WHEN success = 1 THEN 1 ELSE 0 END), 0) as successful_requests,
-            COALESCE(SUM(CASE WHEN success = 0 THEN 1 ELSE 0 END), 0) as failed_requests,
+            COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END), 0) as successful_requests,
+            COALESCE(SUM(CASE WHEN status = 'failed' THEN 1 ELSE 0 END), 0) as failed_requests,
+            COALESCE(SUM(CASE WHEN status = 'cancelled' THEN 1 ELSE 0 END), 0) as cancelled_requests,
+            COALESCE(SUM(CASE WHEN status = 'cached' THEN 1 ELSE 0 END), 0) as cached_requests,
+            COALESCE(SUM(saved_tokens), 0) as tokens_saved,
             COALESCE(AVG(response_time_ms), 0) as avg_response_time
         FROM usage_records {}",
         date_filter
@@ -158,7 +251,8 @@ pub async fn get_usage_stats(app_handle: &AppHandle, days: Option<i32>) -> Resul
         .map_err(|e| format!("Failed to prepare stats query: {}", e))?;
     
     let (total_requests, total_tokens, total_input_tokens, total_output_tokens, 
-         successful_requests, failed_requests, avg_response_time): (i64, i64, i64, i64, i64, i64, f64) = 
+         successful_requests, failed_requests, cancelled_requests, cached_requests, tokens_saved,
+         avg_response_time): (i64, i64, i64, i64, i64, i64, i64, i64, i64, f64) = 
         stmt.query_row([], |row| {
             Ok((
                 row.get(0)?,
@@ -168,6 +262,9 @@ pub async fn get_usage_stats(app_handle: &AppHandle, days: Option<i32>) -> Resul
                 row.get(4)?,
                 row.get(5)?,
                 row.get(6)?,
+                row.get(7)?,
+                row.get(8)?,
+                row.get(9)?,
             ))
         }).map_err(|e| format!("Failed to get overall stats: {}", e))?;
     
@@ -178,8 +275,8 @@ pub async fn get_usage_stats(app_handle: &AppHandle, days: Option<i32>) -> Resul
             model,
             COUNT(*) as total_requests,
             COALESCE(SUM(total_tokens), 0) as total_tokens,
-            COALESCE(SUM(CASE WHEN success = 1 THEN 1 ELSE 0 END), 0) as successful_requests,
-            COALESCE(SUM(CASE WHEN success = 0 THEN 1 ELSE 0 END), 0) as failed_requests
+            COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END), 0) as successful_requests,
+            COALESCE(SUM(CASE WHEN status = 'failed' THEN 1 ELSE 0 END), 0) as failed_requests
         FROM usage_records {}
         GROUP BY provider, model
         ORDER BY total_tokens DESC",
@@ -236,6 +333,9 @@ pub async fn get_usage_stats(app_handle: &AppHandle, days: Option<i32>) -> Resul
         total_output_tokens,
         successful_requests,
         failed_requests,
+        cancelled_requests,
+        cached_requests,
+        tokens_saved,
         average_response_time_ms: avg_response_time,
         by_provider: provider_stats,
         by_tool: tool_stats,
@@ -256,7 +356,8 @@ pub async fn get_usage_history(
     
     let query = "SELECT 
         id, timestamp, provider, model, tool, input_tokens, output_tokens, 
-        total_tokens, success, error_message, response_time_ms
+        total_tokens, success, error_message, response_time_ms, status, saved_tokens,
+        group_id, stage
     FROM usage_records 
     ORDER BY timestamp DESC 
     LIMIT ?1 OFFSET ?2";
@@ -279,6 +380,10 @@ pub async fn get_usage_history(
             success: row.get(8)?,
             error_message: row.get(9)?,
             response_time_ms: row.get(10)?,
+            status: UsageStatus::from_db(&row.get::<_, String>(11)?),
+            saved_tokens: row.get(12)?,
+            group_id: row.get(13)?,
+            stage: row.get(14)?,
         })
     })
     .map_err(|e| format!("Failed to query usage history: {}", e))?
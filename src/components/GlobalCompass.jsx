@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react';
-import { generateAIResponseV2, createAIRequest, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
+import { generateAIResponseV2, createAIRequest, failureMessage, isRetryable, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
 
 export default function GlobalCompass({ onClose }) {
     const [product, setProduct] = useState('');
@@ -11,6 +11,7 @@ export default function GlobalCompass({ onClose }) {
     const [loading, setLoading] = useState(false);
     const [analysis, setAnalysis] = useState(null);
     const [error, setError] = useState(null);
+    const [retryable, setRetryable] = useState(false);
     const [generatedPrompt, setGeneratedPrompt] = useState('');
     const [promptData, setPromptData] = useState(null);
     const [isCustomPrompt, setIsCustomPrompt] = useState(false);
@@ -165,6 +166,7 @@ Please provide specific, actionable insights with concrete data points where pos
 
         setLoading(true);
         setError(null);
+        setRetryable(false);
         setAnalysis(null);
 
         try {
@@ -172,8 +174,6 @@ Please provide specific, actionable insights with concrete data points where pos
             setGeneratedPrompt(prompt);
             
             const aiRequest = createAIRequest(prompt, {
-                temperature: 0.3, // Lower temperature for more factual, structured analysis
-                maxTokens: 3000,
                 systemMessage: promptData?.system_message || "You are an expert international business consultant specializing in market entry strategies. Provide detailed, practical analysis with specific data points and actionable recommendations."
             });
 
@@ -191,7 +191,8 @@ Please provide specific, actionable insights with concrete data points where pos
                     generated: new Date().toLocaleString()
                 });
             } else {
-                setError(response.error || 'Failed to generate market analysis. Please try again.');
+                setError(failureMessage(response, 'Failed to generate market analysis. Please try again.'));
+                setRetryable(isRetryable(response));
             }
         } catch (error) {
             setError('Error generating analysis: ' + error.toString());
@@ -347,6 +348,22 @@ ${section.content}
                             color: '#991b1b'
                         }}>
                             <strong>⚠️ Error:</strong> {error}
+                            {retryable && !loading && (
+                                <button
+                                    onClick={handleAnalyze}
+                                    style={{
+                                        marginLeft: '10px',
+                                        padding: '4px 12px',
+                                        background: 'white',
+                                        color: '#991b1b',
+                                        border: '1px solid #fecaca',
+                                        borderRadius: '6px',
+                                        cursor: 'pointer'
+                                    }}
+                                >
+                                    🔄 Try again
+                                </button>
+                            )}
                         </div>
                     )}
 
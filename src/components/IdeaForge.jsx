@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react';
-import { generateAIResponseV2, createAIRequest, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
+import { generateAIResponseV2, createAIRequest, failureMessage, isRetryable, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
 
 export default function IdeaForge({ onClose }) {
     const [keywords, setKeywords] = useState('');
@@ -9,6 +9,7 @@ export default function IdeaForge({ onClose }) {
     const [loading, setLoading] = useState(false);
     const [ideas, setIdeas] = useState([]);
     const [error, setError] = useState(null);
+    const [retryable, setRetryable] = useState(false);
     const [generatedPrompt, setGeneratedPrompt] = useState('');
     const [promptData, setPromptData] = useState(null);
     const [isCustomPrompt, setIsCustomPrompt] = useState(false);
@@ -93,6 +94,7 @@ Format your response as a numbered list with clear sections for each idea.`;
 
         setLoading(true);
         setError(null);
+        setRetryable(false);
         setIdeas([]);
 
         try {
@@ -101,7 +103,6 @@ Format your response as a numbered list with clear sections for each idea.`;
             
             const aiRequest = createAIRequest(prompt, {
                 temperature: creativity / 10,
-                maxTokens: 2000,
                 systemMessage: promptData?.system_message || "You are an expert business consultant helping entrepreneurs generate innovative business ideas. Provide practical, actionable suggestions."
             });
 
@@ -111,7 +112,8 @@ Format your response as a numbered list with clear sections for each idea.`;
                 const parsedIdeas = parseAIResponse(response.content);
                 setIdeas(parsedIdeas);
             } else {
-                setError(response.error || 'Failed to generate ideas. Please try again.');
+                setError(failureMessage(response, 'Failed to generate ideas. Please try again.'));
+                setRetryable(isRetryable(response));
             }
         } catch (error) {
             setError('Error generating ideas: ' + error.toString());
@@ -271,6 +273,22 @@ ${idea.description}
                             color: '#991b1b'
                         }}>
                             <strong>⚠️ Error:</strong> {error}
+                            {retryable && !loading && (
+                                <button
+                                    onClick={handleGenerate}
+                                    style={{
+                                        marginLeft: '10px',
+                                        padding: '4px 12px',
+                                        background: 'white',
+                                        color: '#991b1b',
+                                        border: '1px solid #fecaca',
+                                        borderRadius: '6px',
+                                        cursor: 'pointer'
+                                    }}
+                                >
+                                    🔄 Try again
+                                </button>
+                            )}
                         </div>
                     )}
 
@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react';
-import { generateAIResponseV2, createAIRequest, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
+import { generateAIResponseV2, createAIRequest, failureMessage, isRetryable, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
 
 export default function PRDGenerator({ onClose }) {
     const [currentStep, setCurrentStep] = useState(1);
@@ -9,6 +9,7 @@ export default function PRDGenerator({ onClose }) {
     const [loading, setLoading] = useState(false);
     const [generatedPRD, setGeneratedPRD] = useState('');
     const [error, setError] = useState(null);
+    const [retryable, setRetryable] = useState(false);
     const [generatedPrompt, setGeneratedPrompt] = useState('');
     const [promptData, setPromptData] = useState(null);
     const [isCustomPrompt, setIsCustomPrompt] = useState(false);
@@ -86,22 +87,23 @@ export default function PRDGenerator({ onClose }) {
         }
     ];
 
-    const generatePrompt = (initialIdea, featureName, answers) => {
-        const formattedAnswers = Object.entries(answers)
+    const promptVariables = (initialIdea, featureName, answers) => ({
+        feature_name: featureName,
+        initial_idea: initialIdea,
+        formatted_answers: Object.entries(answers)
             .map(([key, value]) => {
                 const question = clarifyingQuestions.find(q => q.id === key);
                 return `**${question?.title || key}:** ${value}`;
             })
-            .join('\n\n');
+            .join('\n\n')
+    });
+
+    const generatePrompt = (initialIdea, featureName, answers) => {
+        const variables = promptVariables(initialIdea, featureName, answers);
+        const formattedAnswers = variables.formatted_answers;
 
         // Use stored prompt template if available
         if (promptData && promptData.template) {
-            const variables = {
-                feature_name: featureName,
-                initial_idea: initialIdea,
-                formatted_answers: formattedAnswers
-            };
-            
             return replacePromptVariables(promptData.template, variables);
         }
 
@@ -168,15 +170,20 @@ Make the PRD comprehensive but practical, focusing on clarity and implementabili
     const generatePRD = async () => {
         setLoading(true);
         setError(null);
+        setRetryable(false);
 
         try {
             const prompt = generatePrompt(initialIdea, featureName, answers);
             setGeneratedPrompt(prompt);
             
             const aiRequest = createAIRequest(prompt, {
-                temperature: 0.3, // Lower temperature for more structured, consistent output
-                maxTokens: 4000,
-                systemMessage: promptData?.system_message || "You are an expert product manager specializing in creating comprehensive Product Requirements Documents. Generate clear, actionable PRDs that development teams can follow to build features successfully."
+                systemMessage: promptData?.system_message || "You are an expert product manager specializing in creating comprehensive Product Requirements Documents. Generate clear, actionable PRDs that development teams can follow to build features successfully.",
+                // Lets answers too long for the model be condensed in parts
+                splitInput: promptData?.template ? {
+                    template: promptData.template,
+                    variables: promptVariables(initialIdea, featureName, answers),
+                    variable: 'formatted_answers'
+                } : null
             });
 
             const response = await generateAIResponseV2(aiRequest, 'prd_generator');
@@ -186,7 +193,8 @@ Make the PRD comprehensive but practical, focusing on clarity and implementabili
                 setGeneratedPRD(parsedPRD);
                 setCurrentStep(4);
             } else {
-                setError(response.error || 'Failed to generate PRD. Please try again.');
+                setError(failureMessage(response, 'Failed to generate PRD. Please try again.'));
+                setRetryable(isRetryable(response));
             }
         } catch (error) {
             setError('Error generating PRD: ' + error.toString());
@@ -412,6 +420,22 @@ ${generatedPrompt}
                             color: '#991b1b'
                         }}>
                             <strong>⚠️ Error:</strong> {error}
+                            {retryable && !loading && (
+                                <button
+                                    onClick={generatePRD}
+                                    style={{
+                                        marginLeft: '10px',
+                                        padding: '4px 12px',
+                                        background: 'white',
+                                        color: '#991b1b',
+                                        border: '1px solid #fecaca',
+                                        borderRadius: '6px',
+                                        cursor: 'pointer'
+                                    }}
+                                >
+                                    🔄 Try again
+                                </button>
+                            )}
                         </div>
                     )}
 
@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react';
-import { generateAIResponseV2, createAIRequest, saveFileToDownloads, getPrompt, replacePromptVariables } from '../lib/tauri_frontend_api';
+import { generateAIResponseV2, createAIRequest, failureMessage, isRetryable, saveFileToDownloads, getPrompt, replacePromptVariables, extractDocument } from '../lib/tauri_frontend_api';
 
 export default function PitchPerfect({ onClose }) {
     const [pitchType, setPitchType] = useState('');
@@ -11,9 +11,12 @@ export default function PitchPerfect({ onClose }) {
     const [loading, setLoading] = useState(false);
     const [analysis, setAnalysis] = useState(null);
     const [error, setError] = useState(null);
+    const [retryable, setRetryable] = useState(false);
     const [generatedPrompt, setGeneratedPrompt] = useState('');
     const [promptData, setPromptData] = useState(null);
     const [isCustomPrompt, setIsCustomPrompt] = useState(false);
+    const [slides, setSlides] = useState([]);
+    const [importingDocument, setImportingDocument] = useState(false);
 
     // Load prompt data on component mount
     useEffect(() => {
@@ -60,7 +63,7 @@ export default function PitchPerfect({ onClose }) {
         { value: '30+ minutes', label: '📈 30+ minutes (Detailed)' }
     ];
 
-    const generatePrompt = (pitchType, audience, duration, industry, content, feedbackStyle) => {
+    const promptVariables = (pitchType, audience, duration, industry, content, feedbackStyle) => {
         const feedbackDescriptors = {
             1: "very gentle and supportive", 2: "gentle and encouraging", 3: "supportive and constructive",
             4: "constructive and balanced", 5: "balanced and direct", 6: "direct and detailed",
@@ -69,19 +72,24 @@ export default function PitchPerfect({ onClose }) {
         };
         
         const feedbackLevel = feedbackDescriptors[feedbackStyle] || "balanced and direct";
-        
+
+        return {
+            pitch_type: pitchType,
+            audience: audience,
+            pitch_content: content,
+            feedback_style: feedbackStyle.toString(),
+            feedback_level: feedbackLevel,
+            duration_section: duration ? ` (${duration})` : '',
+            industry_section: industry ? ` in the ${industry} industry` : ''
+        };
+    };
+
+    const generatePrompt = (pitchType, audience, duration, industry, content, feedbackStyle) => {
+        const variables = promptVariables(pitchType, audience, duration, industry, content, feedbackStyle);
+        const feedbackLevel = variables.feedback_level;
+
         // Use stored prompt template if available
         if (promptData && promptData.template) {
-            const variables = {
-                pitch_type: pitchType,
-                audience: audience,
-                pitch_content: content,
-                feedback_style: feedbackStyle.toString(),
-                feedback_level: feedbackLevel,
-                duration_section: duration ? ` (${duration})` : '',
-                industry_section: industry ? ` in the ${industry} industry` : ''
-            };
-            
             return replacePromptVariables(promptData.template, variables);
         }
         
@@ -165,6 +173,50 @@ Provide specific, actionable advice that helps improve presentation effectivenes
         return section.trim() || 'Analysis provided in main content.';
     };
 
+    const handleSlidesSelected = async (e) => {
+        const files = Array.from(e.target.files || []);
+        e.target.value = '';
+        const read = (file) => new Promise((resolve, reject) => {
+            const reader = new FileReader();
+            reader.onload = () => resolve({ name: file.name, data: reader.result });
+            reader.onerror = () => reject(reader.error);
+            reader.readAsDataURL(file);
+        });
+        try {
+            const loaded = await Promise.all(files.map(read));
+            setSlides(prev => [...prev, ...loaded]);
+        } catch (error) {
+            setError('Error reading slide images: ' + error.toString());
+        }
+    };
+
+    const handleDocumentSelected = async (e) => {
+        const file = e.target.files?.[0];
+        e.target.value = '';
+        if (!file) return;
+
+        setImportingDocument(true);
+        setError(null);
+        try {
+            const data = await new Promise((resolve, reject) => {
+                const reader = new FileReader();
+                reader.onload = () => resolve(reader.result);
+                reader.onerror = () => reject(reader.error);
+                reader.readAsDataURL(file);
+            });
+            const result = await extractDocument({ fileName: file.name, data });
+            if (result.success) {
+                setPitchContent(result.document.text);
+            } else {
+                setError(result.error);
+            }
+        } catch (error) {
+            setError('Error reading document: ' + error.toString());
+        } finally {
+            setImportingDocument(false);
+        }
+    };
+
     const handleAnalyze = async () => {
         if (!pitchContent.trim()) {
             setError('Please enter your pitch content!');
@@ -178,6 +230,7 @@ Provide specific, actionable advice that helps improve presentation effectivenes
 
         setLoading(true);
         setError(null);
+        setRetryable(false);
         setAnalysis(null);
 
         try {
@@ -185,9 +238,15 @@ Provide specific, actionable advice that helps improve presentation effectivenes
             setGeneratedPrompt(prompt);
             
             const aiRequest = createAIRequest(prompt, {
-                temperature: 0.3, // Lower temperature for more structured, consistent feedback
-                maxTokens: 3000,
-                systemMessage: promptData?.system_message || "You are an expert presentation coach specializing in business pitches. Provide specific, actionable feedback with clear scores and concrete suggestions for improvement."
+                systemMessage: promptData?.system_message || "You are an expert presentation coach specializing in business pitches. Provide specific, actionable feedback with clear scores and concrete suggestions for improvement.",
+                // Lets a pitch too long for the model be reviewed in parts
+                splitInput: promptData?.template ? {
+                    template: promptData.template,
+                    variables: promptVariables(pitchType, audience, duration, industry, pitchContent, feedbackStyle),
+                    variable: 'pitch_content'
+                } : null,
+                // Slides are reviewed alongside the script when the model supports images
+                images: slides.map(slide => ({ data: slide.data }))
             });
 
             const response = await generateAIResponseV2(aiRequest, 'pitch_perfect');
@@ -206,7 +265,8 @@ Provide specific, actionable advice that helps improve presentation effectivenes
                     fullResponse: response.content
                 });
             } else {
-                setError(response.error || 'Failed to analyze pitch. Please try again.');
+                setError(failureMessage(response, 'Failed to analyze pitch. Please try again.'));
+                setRetryable(isRetryable(response));
             }
         } catch (error) {
             setError('Error analyzing pitch: ' + error.toString());
@@ -372,6 +432,22 @@ ${analysis.fullResponse}
                             color: '#991b1b'
                         }}>
                             <strong>⚠️ Error:</strong> {error}
+                            {retryable && !loading && (
+                                <button
+                                    onClick={handleAnalyze}
+                                    style={{
+                                        marginLeft: '10px',
+                                        padding: '4px 12px',
+                                        background: 'white',
+                                        color: '#991b1b',
+                                        border: '1px solid #fecaca',
+                                        borderRadius: '6px',
+                                        cursor: 'pointer'
+                                    }}
+                                >
+                                    🔄 Try again
+                                </button>
+                            )}
                         </div>
                     )}
 
@@ -495,6 +571,16 @@ ${analysis.fullResponse}
                         <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
                             📝 Your Pitch Content:
                         </label>
+                        <div style={{ marginBottom: '8px', fontSize: '14px', color: '#666' }}>
+                            📎 Or load it from your deck or script (PDF, DOCX, PPTX, Markdown):{' '}
+                            <input
+                                type="file"
+                                accept=".pdf,.docx,.pptx,.md,.markdown,.txt"
+                                onChange={handleDocumentSelected}
+                                disabled={importingDocument}
+                            />
+                            {importingDocument && <span> Extracting text...</span>}
+                        </div>
                         <textarea
                             value={pitchContent}
                             onChange={(e) => setPitchContent(e.target.value)}
@@ -528,6 +614,40 @@ Example:
                         </div>
                     </div>
 
+                    {/* Row 3b: Optional slide images */}
+                    <div style={{ marginBottom: '20px' }}>
+                        <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
+                            🖼️ Slide Images (optional, needs a vision model):
+                        </label>
+                        <input
+                            type="file"
+                            accept="image/png,image/jpeg,image/gif,image/webp"
+                            multiple
+                            onChange={handleSlidesSelected}
+                        />
+                        {slides.length > 0 && (
+                            <div style={{ display: 'flex', flexWrap: 'wrap', gap: '10px', marginTop: '10px' }}>
+                                {slides.map((slide, index) => (
+                                    <div key={index} style={{ textAlign: 'center', fontSize: '12px', color: '#666' }}>
+                                        <img
+                                            src={slide.data}
+                                            alt={slide.name}
+                                            style={{ width: '120px', height: '68px', objectFit: 'cover', borderRadius: '6px', border: '1px solid #e1e5e9' }}
+                                        />
+                                        <div>
+                                            <button
+                                                onClick={() => setSlides(prev => prev.filter((_, i) => i !== index))}
+                                                style={{ background: 'none', border: 'none', color: '#ef4444', cursor: 'pointer', fontSize: '12px' }}
+                                            >
+                                                Remove
+                                            </button>
+                                        </div>
+                                    </div>
+                                ))}
+                            </div>
+                        )}
+                    </div>
+
                     {/* Row 4: Feedback Style Slider */}
                     <div style={{ marginBottom: '25px' }}>
                         <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
@@ -13,6 +13,19 @@ import {
     saveFileToDownloads
 } from '../lib/tauri_frontend_api';
 
+const PROVIDER_INFO = {
+    ollama: { name: 'Ollama (Local)', desc: 'Free, runs on your computer' },
+    openai: { name: 'OpenAI', desc: 'ChatGPT/GPT-4 - Requires API key' },
+    anthropic: { name: 'Anthropic', desc: 'Claude - Requires API key' },
+    gemini: { name: 'Google Gemini', desc: 'Gemini Pro - Requires API key' },
+    openai_compatible: { name: 'OpenAI-Compatible Server', desc: 'LM Studio, vLLM, llama.cpp or any other server with an OpenAI-style API' },
+    azure: { name: 'Azure OpenAI', desc: 'OpenAI models deployed in your Azure resource - Requires API key' },
+    mock: { name: 'Mock (Offline)', desc: 'Canned responses for demos and workshops - no model needed' }
+};
+
+// Providers whose model names can't be listed reliably, so the model is typed in.
+const TYPED_MODEL_PROVIDERS = ['openai_compatible', 'azure'];
+
 export default function SettingsPage({ onClose }) {
     const [settings, setSettings] = useState(null);
     const [saving, setSaving] = useState(false);
@@ -45,6 +58,7 @@ export default function SettingsPage({ onClose }) {
         try {
             // Create a test config with current form values instead of saved settings
             const currentConfig = {
+                ...settings[providerType],
                 provider_type: providerType,
                 api_key: settings[providerType]?.api_key || null,
                 base_url: settings[providerType]?.base_url || DEFAULT_PROVIDERS[providerType]?.base_url,
@@ -282,13 +296,8 @@ export default function SettingsPage({ onClose }) {
                     <div style={{ marginBottom: '30px' }}>
                         <h3 style={{ color: '#333', marginBottom: '15px' }}>AI Provider</h3>
                         
-                        {['ollama', 'openai', 'anthropic', 'gemini'].map(provider => {
-                            const providerInfo = {
-                                ollama: { name: 'Ollama (Local)', desc: 'Free, runs on your computer' },
-                                openai: { name: 'OpenAI', desc: 'ChatGPT/GPT-4 - Requires API key' },
-                                anthropic: { name: 'Anthropic', desc: 'Claude - Requires API key' },
-                                gemini: { name: 'Google Gemini', desc: 'Gemini Pro - Requires API key' }
-                            }[provider];
+                        {Object.keys(PROVIDER_INFO).map(provider => {
+                            const providerInfo = PROVIDER_INFO[provider];
                             
                             return (
                                 <label key={provider} style={{ 
@@ -333,15 +342,36 @@ export default function SettingsPage({ onClose }) {
                         })}
                     </div>
 
-                    {settings.preferred_provider !== 'ollama' && (
+                    {!['ollama', 'mock'].includes(settings.preferred_provider) && (
                         <div style={{ marginBottom: '30px' }}>
                             <h3 style={{ color: '#333', marginBottom: '15px' }}>
-                                {settings.preferred_provider.charAt(0).toUpperCase() + settings.preferred_provider.slice(1)} Configuration
+                                {PROVIDER_INFO[settings.preferred_provider]?.name || settings.preferred_provider} Configuration
                             </h3>
+
+                            {settings.preferred_provider === 'openai_compatible' && (
+                                <div style={{ marginBottom: '20px' }}>
+                                    <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
+                                        Server Name:
+                                    </label>
+                                    <input
+                                        type="text"
+                                        value={settings.openai_compatible?.display_name || ''}
+                                        onChange={(e) => updateProviderConfig('openai_compatible', 'display_name', e.target.value || null)}
+                                        placeholder="e.g. LM Studio"
+                                        style={{
+                                            width: '100%',
+                                            padding: '12px',
+                                            border: '2px solid #e1e5e9',
+                                            borderRadius: '8px',
+                                            fontSize: '16px'
+                                        }}
+                                    />
+                                </div>
+                            )}
                             
                             <div style={{ marginBottom: '20px' }}>
                                 <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
-                                    API Key:
+                                    API Key{settings.preferred_provider === 'openai_compatible' ? ' (optional for local servers)' : ''}:
                                 </label>
                                 <div style={{ display: 'flex', gap: '10px' }}>
                                     <input
@@ -399,12 +429,15 @@ export default function SettingsPage({ onClose }) {
                                     {settings.preferred_provider === 'gemini' && (
                                         <>Get your API key from <a href="https://makersuite.google.com/app/apikey" target="_blank" rel="noopener">Google AI Studio</a></>
                                     )}
+                                    {settings.preferred_provider === 'azure' && (
+                                        <>Find the key under Keys and Endpoint of your resource in the <a href="https://portal.azure.com/" target="_blank" rel="noopener">Azure Portal</a></>
+                                    )}
                                 </small>
                             </div>
                             
                             <div style={{ marginBottom: '20px' }}>
                                 <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
-                                    Base URL:
+                                    {settings.preferred_provider === 'azure' ? 'Endpoint:' : 'Base URL:'}
                                 </label>
                                 <input
                                     type="text"
@@ -424,42 +457,113 @@ export default function SettingsPage({ onClose }) {
                                 </small>
                             </div>
                             
+                            {settings.preferred_provider === 'azure' && (
+                                <>
+                                    <div style={{ marginBottom: '20px' }}>
+                                        <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
+                                            Deployment:
+                                        </label>
+                                        <input
+                                            type="text"
+                                            value={settings.azure?.deployment || ''}
+                                            onChange={(e) => updateProviderConfig('azure', 'deployment', e.target.value || null)}
+                                            placeholder="e.g. prod-gpt4o"
+                                            style={{
+                                                width: '100%',
+                                                padding: '12px',
+                                                border: '2px solid #e1e5e9',
+                                                borderRadius: '8px',
+                                                fontSize: '16px'
+                                            }}
+                                        />
+                                        <small style={{ display: 'block', marginTop: '5px', color: '#666', fontSize: '0.9em' }}>
+                                            The deployment name from Azure AI Foundry; defaults to the model name below
+                                        </small>
+                                    </div>
+                                    <div style={{ marginBottom: '20px' }}>
+                                        <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
+                                            API Version:
+                                        </label>
+                                        <input
+                                            type="text"
+                                            value={settings.azure?.api_version || ''}
+                                            onChange={(e) => updateProviderConfig('azure', 'api_version', e.target.value || null)}
+                                            placeholder="2024-10-21"
+                                            style={{
+                                                width: '100%',
+                                                padding: '12px',
+                                                border: '2px solid #e1e5e9',
+                                                borderRadius: '8px',
+                                                fontSize: '16px'
+                                            }}
+                                        />
+                                        <small style={{ display: 'block', marginTop: '5px', color: '#666', fontSize: '0.9em' }}>
+                                            Leave empty for 2024-10-21
+                                        </small>
+                                    </div>
+                                </>
+                            )}
+
                             <div style={{ marginBottom: '20px' }}>
                                 <label style={{ display: 'block', marginBottom: '8px', fontWeight: '600', color: '#333' }}>
                                     Model:
                                 </label>
-                                <select
-                                    value={settings[settings.preferred_provider]?.model || ''}
-                                    onChange={(e) => updateProviderConfig(settings.preferred_provider, 'model', e.target.value)}
-                                    style={{
-                                        width: '100%',
-                                        padding: '12px',
-                                        border: '2px solid #e1e5e9',
-                                        borderRadius: '8px',
-                                        fontSize: '16px'
-                                    }}
-                                >
-                                    {(() => {
-                                        const currentModel = settings[settings.preferred_provider]?.model;
-                                        const models = availableModels[settings.preferred_provider] || [];
-                                        
-                                        // If current model is not in the list, add it as the first option
-                                        if (currentModel && !models.includes(currentModel)) {
-                                            return [
-                                                <option key={currentModel} value={currentModel}>
-                                                    {currentModel} (current)
-                                                </option>,
-                                                ...models.map(model => (
-                                                    <option key={model} value={model}>{model}</option>
-                                                ))
-                                            ];
-                                        }
-                                        
-                                        return models.map(model => (
-                                            <option key={model} value={model}>{model}</option>
-                                        ));
-                                    })()}
-                                </select>
+                                {TYPED_MODEL_PROVIDERS.includes(settings.preferred_provider) ? (
+                                    <>
+                                        <input
+                                            type="text"
+                                            list={`models-${settings.preferred_provider}`}
+                                            value={settings[settings.preferred_provider]?.model || ''}
+                                            onChange={(e) => updateProviderConfig(settings.preferred_provider, 'model', e.target.value)}
+                                            placeholder={DEFAULT_PROVIDERS[settings.preferred_provider]?.default_model}
+                                            style={{
+                                                width: '100%',
+                                                padding: '12px',
+                                                border: '2px solid #e1e5e9',
+                                                borderRadius: '8px',
+                                                fontSize: '16px'
+                                            }}
+                                        />
+                                        <datalist id={`models-${settings.preferred_provider}`}>
+                                            {(availableModels[settings.preferred_provider] || []).map(model => (
+                                                <option key={model} value={model} />
+                                            ))}
+                                        </datalist>
+                                    </>
+                                ) : (
+                                    <select
+                                        value={settings[settings.preferred_provider]?.model || ''}
+                                        onChange={(e) => updateProviderConfig(settings.preferred_provider, 'model', e.target.value)}
+                                        style={{
+                                            width: '100%',
+                                            padding: '12px',
+                                            border: '2px solid #e1e5e9',
+                                            borderRadius: '8px',
+                                            fontSize: '16px'
+                                        }}
+                                    >
+                                        {(() => {
+                                            const currentModel = settings[settings.preferred_provider]?.model;
+                                            const models = availableModels[settings.preferred_provider] || [];
+                                            
+                                            // If current model is not in the list, add it as the first option
+                                            if (currentModel && !models.includes(currentModel)) {
+                                                return [
+                                                    <option key={currentModel} value={currentModel}>
+                                                        {currentModel} (current)
+                                                    </option>,
+                                                    ...models.map(model => (
+                                                        <option key={model} value={model}>{model}</option>
+                                                    ))
+                                                ];
+                                            }
+                                            
+                                            return models.map(model => (
+                                                <option key={model} value={model}>{model}</option>
+                                            ));
+                                        })()}
+                                    </select>
+                                )}
                             </div>
                         </div>
                     )}
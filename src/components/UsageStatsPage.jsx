@@ -248,6 +248,23 @@ export default function UsageStatsPage({ onClose }) {
                                         </div>
                                         <div style={{ fontSize: '0.9em', color: '#9f1239' }}>Output Tokens</div>
                                     </div>
+
+                                    {usageStats.cached_requests > 0 && (
+                                        <div style={{
+                                            padding: '15px',
+                                            backgroundColor: '#f5f3ff',
+                                            border: '2px solid #8b5cf6',
+                                            borderRadius: '8px',
+                                            textAlign: 'center'
+                                        }}>
+                                            <div style={{ fontSize: '1.5em', fontWeight: '700', color: '#8b5cf6' }}>
+                                                {(usageStats.tokens_saved || 0).toLocaleString()}
+                                            </div>
+                                            <div style={{ fontSize: '0.9em', color: '#5b21b6' }}>
+                                                Tokens Saved ({usageStats.cached_requests} cache hits)
+                                            </div>
+                                        </div>
+                                    )}
                                 </div>
 
                                 {/* Provider Breakdown */}
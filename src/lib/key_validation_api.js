@@ -2,7 +2,7 @@ import { invoke } from '@tauri-apps/api/core';
 
 /**
  * Validates an API key format for a specific provider
- * @param {string} provider - The provider name (openai, anthropic, gemini, ollama)
+ * @param {string} provider - The provider name (openai, anthropic, gemini, ollama, openai_compatible, azure)
  * @param {string} apiKey - The API key to validate
  * @returns {Promise<{is_valid: boolean, error_message: string|null, masked_key: string}>}
  */
@@ -83,6 +83,20 @@ export function getKeyValidationPattern(provider) {
         placeholder: 'Optional bearer token',
         helpText: 'Ollama can work without authentication or with any bearer token format'
       };
+    case 'openai_compatible':
+      return {
+        pattern: /^\S+$/, // Optional, any token without whitespace
+        minLength: 0,
+        placeholder: 'Optional bearer token',
+        helpText: 'OpenAI-compatible servers accept any bearer token, or none for local servers'
+      };
+    case 'azure':
+      return {
+        pattern: /^[a-zA-Z0-9]+$/,
+        minLength: 32,
+        placeholder: 'Resource key from Keys and Endpoint',
+        helpText: 'Azure OpenAI key should be at least 32 alphanumeric characters'
+      };
     default:
       return {
         pattern: null,
@@ -98,7 +112,7 @@ export function quickValidateKey(provider, apiKey) {
   const validation = getKeyValidationPattern(provider);
   const trimmedKey = apiKey.trim();
   
-  if (!trimmedKey && provider.toLowerCase() === 'ollama') {
+  if (!trimmedKey && ['ollama', 'openai_compatible'].includes(provider.toLowerCase())) {
     return { valid: true, message: null };
   }
   
@@ -1,5 +1,6 @@
 // src/lib/tauri_frontend_api.js
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 
 // Settings Management
 export async function saveSettings(settings) {
@@ -29,6 +30,8 @@ export async function loadSettings() {
 export function getDefaultSettings() {
     return {
         preferred_provider: 'ollama',
+        // Tried in order when the preferred provider is rate limited, down, or out of quota
+        fallback_providers: [],
         openai: {
             provider_type: 'openai',
             base_url: 'https://api.openai.com/v1',
@@ -51,7 +54,69 @@ export function getDefaultSettings() {
             provider_type: 'ollama',
             base_url: 'http://localhost:11434',
             model: 'llama3.1',
-            enabled: true
+            enabled: true,
+            // null keeps the model's Modelfile default (see getOllamaModelDefaults)
+            ollama_options: {
+                num_ctx: null,
+                top_k: null,
+                repeat_penalty: null,
+                keep_alive: null
+            }
+        },
+        openai_compatible: {
+            provider_type: 'openai_compatible',
+            base_url: 'http://localhost:1234/v1',
+            model: 'local-model',
+            enabled: false,
+            display_name: 'OpenAI-compatible'
+        },
+        azure: {
+            provider_type: 'azure',
+            base_url: 'https://your-resource.openai.azure.com',
+            model: 'gpt-4o',
+            enabled: false,
+            display_name: 'Azure OpenAI',
+            deployment: null, // Defaults to the model name
+            api_version: '2024-10-21'
+        },
+        // Offline provider for demos; no key or server needed
+        mock: {
+            provider_type: 'mock',
+            base_url: '',
+            model: 'mock-model',
+            enabled: false,
+            display_name: 'Mock (offline)',
+            mock_options: {
+                latency_ms: 400,
+                chunk_delay_ms: 20,
+                fail_with: null, // e.g. 'RateLimit' to demo retries and fallbacks
+                fail_times: 0, // fail only the first N attempts; 0 = always
+                retry_after: null,
+                responses: {} // tool id -> template with {prompt} and {model}
+            }
+        },
+        // Opt-in cache of identical requests; see AIRequest.bypass_cache to skip it once
+        cache: {
+            enabled: false,
+            ttl_hours: 168,
+            max_entries: 1000,
+            max_size_mb: 50
+        },
+        // Inputs too long for the model are condensed in parts for these tools
+        map_reduce: {
+            tools: {
+                pitch_perfect: { enabled: true, instruction: null },
+                prd_generator: { enabled: true, instruction: null }
+            },
+            max_chunks: 12
+        },
+        // Per tool: provider and model (null = the preferred provider and its model), and
+        // the temperature / max_tokens used when a request doesn't set its own
+        tool_routing: {
+            idea_forge: { provider: null, model: null, temperature: 0.7, max_tokens: 2000 },
+            global_compass: { provider: null, model: null, temperature: 0.3, max_tokens: 3000 },
+            pitch_perfect: { provider: null, model: null, temperature: 0.3, max_tokens: 3000 },
+            prd_generator: { provider: null, model: null, temperature: 0.3, max_tokens: 4000 }
         }
     };
 }
@@ -146,6 +211,48 @@ export async function testCustomProviderConnection(providerConfig) {
     }
 }
 
+// AI commands reject with { error_type, code, message, provider, status_code, retry_after }.
+// Branch on code (e.g. 'rate_limit', 'api_key_missing', 'schema_mismatch'), not on message.
+// Failed responses carry the same object as error_details.
+function errorDetails(error) {
+    return typeof error === 'object' && error !== null && error.code ? error : null;
+}
+
+function errorMessage(error) {
+    return errorDetails(error)?.message ?? error.toString();
+}
+
+// Codes of failures that may not happen again: rate limits, outages, dropped connections.
+// A bad key or bad input would fail the same way, so the UI offers no retry for those.
+const RETRYABLE_CODES = ['rate_limit', 'server_error', 'network_error', 'stream_interrupted'];
+
+// What to show for a failed response, or null when there is nothing to report because
+// the user cancelled it.
+export function failureMessage(response, fallback) {
+    if (response.error_details?.code === 'cancelled') {
+        return null;
+    }
+    return response.error || fallback;
+}
+
+export function isRetryable(response) {
+    return RETRYABLE_CODES.includes(response.error_details?.code);
+}
+
+function failedResponse(error) {
+    const details = errorDetails(error);
+    return {
+        content: '',
+        provider: details?.provider || 'error',
+        model: '',
+        success: false,
+        error: errorMessage(error),
+        error_type: details?.error_type ?? null,
+        error_details: details,
+        usage: null
+    };
+}
+
 // Model listing
 export async function listAvailableModels(providerType) {
     try {
@@ -153,7 +260,52 @@ export async function listAvailableModels(providerType) {
         return { success: true, models };
     } catch (error) {
         console.error('Failed to list models:', error);
-        return { success: false, models: [], error: error.toString() };
+        return { success: false, models: [], error: errorMessage(error), error_details: errorDetails(error) };
+    }
+}
+
+// Model capabilities: { context_window, max_output_tokens, vision, json_mode, tool_calling, source }
+// where source is 'provider', 'bundled', 'override' or 'default'; omit the model for the configured one
+export async function getModelCapabilities(providerType, model = null) {
+    try {
+        const capabilities = await invoke('get_model_capabilities', { providerType, model });
+        return { success: true, capabilities };
+    } catch (error) {
+        console.error('Failed to get model capabilities:', error);
+        return { success: false, capabilities: null, error: errorMessage(error), error_details: errorDetails(error) };
+    }
+}
+
+// Pre-flight token count: { prompt_tokens, max_output_tokens, context_window, method, capabilities_source, fits, warning }
+// where method is 'cl100k_base', 'o200k_base' or 'heuristic'; defaults to the preferred provider's model
+export async function estimateTokens(request, providerType = null, model = null) {
+    try {
+        const estimate = await invoke('estimate_tokens', { request, providerType, model });
+        return { success: true, estimate };
+    } catch (error) {
+        console.error('Failed to estimate tokens:', error);
+        return { success: false, estimate: null, error: errorMessage(error), error_details: errorDetails(error) };
+    }
+}
+
+// User corrections, keyed by "provider_type/model", e.g. { 'ollama/llama3.1:8b': { context_window: 32768 } }
+export async function loadModelCapabilityOverrides() {
+    return await invoke('load_model_capability_overrides');
+}
+
+export async function saveModelCapabilityOverrides(overrides) {
+    return await invoke('save_model_capability_overrides', { overrides });
+}
+
+// Modelfile defaults ({ model, options, context_length, capabilities }) for an Ollama model;
+// omit the model to describe the configured one
+export async function getOllamaModelDefaults(model = null) {
+    try {
+        const defaults = await invoke('get_ollama_model_defaults', { model });
+        return { success: true, defaults };
+    } catch (error) {
+        console.error('Failed to read Ollama model defaults:', error);
+        return { success: false, defaults: null, error: errorMessage(error), error_details: errorDetails(error) };
     }
 }
 
@@ -179,8 +331,8 @@ export async function generateAIResponse(prompt, temperature = 0.7, toolType = '
     }
 }
 
-// AI Generation (v2 - new API)
-export async function generateAIResponseV2(request, toolType = 'unknown') {
+// AI Generation (v2 - new API). Pass a requestId to be able to cancelGeneration() it.
+export async function generateAIResponseV2(request, toolType = 'unknown', requestId = null) {
     try {
         // Add tool type to context for usage tracking
         const requestWithTool = {
@@ -190,29 +342,145 @@ export async function generateAIResponseV2(request, toolType = 'unknown') {
                 tool: toolType
             }
         };
-        const response = await invoke('generate_ai_response_v2', { request: requestWithTool });
+        const response = await invoke('generate_ai_response_v2', { request: requestWithTool, requestId });
         return response;
     } catch (error) {
         console.error('Failed to generate AI response v2:', error);
+        return failedResponse(error);
+    }
+}
+
+// AI Generation (streaming) - onChunk receives each text delta as it arrives
+export async function generateAIResponseStream(request, toolType = 'unknown', onChunk = () => {}, requestId = crypto.randomUUID()) {
+    const unlisten = await listen('ai-stream-chunk', (event) => {
+        if (event.payload.request_id === requestId && !event.payload.done) {
+            onChunk(event.payload.delta);
+        }
+    });
+    try {
+        const requestWithTool = {
+            ...request,
+            context: {
+                ...request.context,
+                tool: toolType
+            }
+        };
+        return await invoke('generate_ai_response_stream', { request: requestWithTool, requestId });
+    } catch (error) {
+        console.error('Failed to stream AI response:', error);
+        return failedResponse(error);
+    } finally {
+        unlisten();
+    }
+}
+
+// Stop an in-flight generation started with a requestId
+export async function cancelGeneration(requestId) {
+    try {
+        const cancelled = await invoke('cancel_generation', { requestId });
+        return { success: true, cancelled };
+    } catch (error) {
+        console.error('Failed to cancel generation:', error);
+        return { success: false, cancelled: false, error: error.toString() };
+    }
+}
+
+// Send one request to several providers at once. targets: [{ provider_type, model? }].
+// Resolves to one { index, provider, model, response, latency_ms } per target, in order;
+// onResult gets each leg as soon as it finishes.
+export async function compareProviders(request, targets, onResult = () => {}, requestId = crypto.randomUUID()) {
+    const unlisten = await listen('ai-compare-result', (event) => {
+        if (event.payload.request_id === requestId) {
+            onResult(event.payload);
+        }
+    });
+    try {
+        return await invoke('compare_providers', { request, targets, requestId });
+    } catch (error) {
+        console.error('Failed to compare providers:', error);
+        throw error;
+    } finally {
+        unlisten();
+    }
+}
+
+// Typed tool results: { result, response, repair_attempts }. result is null if the
+// model's output could not be parsed even after asking it to repair it.
+async function generateToolResult(command, request, requestId) {
+    try {
+        return await invoke(command, { request, requestId });
+    } catch (error) {
+        console.error(`Failed to run ${command}:`, error);
         return {
-            content: '',
-            provider: 'error',
-            model: '',
-            success: false,
-            error: error.toString(),
-            usage: null
+            result: null,
+            response: failedResponse(error),
+            repair_attempts: 0
         };
     }
 }
 
+// result: { ideas: [{ name, description, target_market, value_proposition, challenge }] }
+export async function generateBusinessIdeas(request, requestId = null) {
+    return generateToolResult('generate_business_ideas', request, requestId);
+}
+
+// result: { market_opportunity, cultural_considerations, regulatory_requirements,
+//           competitive_landscape, entry_strategy[], risks[], success_factors[], kpis[] }
+export async function analyzeMarket(request, requestId = null) {
+    return generateToolResult('analyze_market', request, requestId);
+}
+
+// result: { scores: { clarity, persuasiveness, audience_fit, call_to_action }, strengths[],
+//           improvements[], suggestions[], rewrite_suggestions[] }
+export async function scorePitch(request, requestId = null) {
+    return generateToolResult('score_pitch', request, requestId);
+}
+
+// result: { feature_name, executive_summary, ... acceptance_criteria }; response.content is the markdown
+export async function generatePrd(request, requestId = null) {
+    return generateToolResult('generate_prd', request, requestId);
+}
+
 // Helper to create AI request with defaults
 export function createAIRequest(prompt, options = {}) {
     return {
         prompt,
-        temperature: options.temperature || 0.7,
-        max_tokens: options.maxTokens || 2000,
+        // Left unset, these come from the tool's routing in settings
+        temperature: options.temperature ?? null,
+        max_tokens: options.maxTokens ?? null,
+        // Optional sampling; each provider clamps or drops what it doesn't support and
+        // reports what it actually used in response.sampling
+        top_p: options.topP ?? null,
+        top_k: options.topK ?? null,
+        stop: options.stop || [],
+        seed: options.seed ?? null,
+        presence_penalty: options.presencePenalty ?? null,
+        frequency_penalty: options.frequencyPenalty ?? null,
         system_message: options.systemMessage || null,
-        context: options.context || null
+        context: options.context || null,
+        // Prior turns as [{ role: 'user' | 'assistant' | 'system', content }]
+        messages: options.messages || [],
+        // JSON Schema for structured output; the parsed result comes back as response.parsed
+        response_schema: options.responseSchema || null,
+        // Skip the response cache, e.g. for an explicit "regenerate"
+        bypass_cache: options.bypassCache || false,
+        // { template, variables, variable }: the prompt as its template, so that when it is
+        // too long for the model the backend can condense `variable` in parts and re-render
+        split_input: options.splitInput || null,
+        // Target another enabled provider and/or model for this request only
+        provider: options.provider || null,
+        model: options.model || null,
+        // { count, ranking: 'none' | 'heuristic' | 'model' }: several independent answers,
+        // returned best first in response.candidates (content is the best one)
+        candidates: options.candidates || null,
+        // [{ path } | { data }]: images for vision models, where data is base64 or a data URL
+        // (PNG, JPEG, GIF or WebP, up to 5 MB each); sent with the prompt
+        images: options.images || [],
+        // Functions the model may call before answering, by name (e.g. 'market_size',
+        // 'country_profile'); the backend runs them and reports each in response.tool_runs
+        tools: (options.tools || []).map(t => typeof t === 'string' ? { name: t } : t),
+        // 'auto' | 'none': 'none' keeps the functions defined but makes the model answer
+        tool_choice: options.toolChoice || 'auto'
     };
 }
 
@@ -247,6 +515,21 @@ export const DEFAULT_PROVIDERS = {
         base_url: 'http://localhost:11434',
         models: [], // Models will be loaded from the server
         default_model: 'llama3.1'
+    },
+    openai_compatible: {
+        base_url: 'http://localhost:1234/v1', // LM Studio; vLLM uses :8000/v1, llama.cpp :8080/v1
+        models: [], // Models will be loaded from the server
+        default_model: 'local-model'
+    },
+    azure: {
+        base_url: 'https://your-resource.openai.azure.com',
+        models: [], // Requests go to the configured deployment
+        default_model: 'gpt-4o'
+    },
+    mock: {
+        base_url: '',
+        models: [],
+        default_model: 'mock-model'
     }
 };
 
@@ -261,6 +544,20 @@ export async function saveFileToDownloads(filename, content) {
     }
 }
 
+// Extracts the text of a PDF, DOCX, PPTX or Markdown file locally. Pass { path } or, from a
+// file input, { fileName, data } with data as base64 or a data URL. Resolves to
+// { file_name, format, sections: [{ title, text }], text, word_count }, where text can be
+// used directly as a prompt variable such as pitch_content
+export async function extractDocument({ path = null, fileName = null, data = null }) {
+    try {
+        const document = await invoke('extract_document', { path, fileName, data });
+        return { success: true, document };
+    } catch (error) {
+        console.error('Failed to extract document:', error);
+        return { success: false, error: error.toString() };
+    }
+}
+
 // Utility Functions for Different Tools
 export function generateIdeaForgePrompt(keywords, context, creativity) {
     let creativityDescriptor = creativity <= 3 ? "conservative and practical" : 
@@ -592,6 +889,27 @@ export async function exportUsageData() {
     }
 }
 
+// Response cache: { entries, size_bytes, total_hits }
+export async function getResponseCacheStats() {
+    try {
+        const stats = await invoke('get_response_cache_stats');
+        return { success: true, stats };
+    } catch (error) {
+        console.error('Failed to get response cache stats:', error);
+        return { success: false, error: error.toString(), stats: null };
+    }
+}
+
+export async function clearResponseCache() {
+    try {
+        await invoke('clear_response_cache');
+        return { success: true };
+    } catch (error) {
+        console.error('Failed to clear response cache:', error);
+        return { success: false, error: error.toString() };
+    }
+}
+
 // React Hook for Usage Stats
 export function useUsageStats(refreshInterval = null) {
     const [stats, setStats] = useState(null);